@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task;
+use tokio::time::Instant;
+
+use crate::database::Database;
+use crate::logging;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long the document has to stay unchanged before we save it.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// The longest we hold on to unsaved changes while more keep coming in.
+const MAX_INTERVAL: Duration = Duration::from_secs(30);
+
+pub struct Autosave {
+    database: Arc<Database>,
+    path: PathBuf,
+}
+
+impl Autosave {
+    pub fn new(database: Arc<Database>, path: PathBuf) -> Arc<Self> {
+        Arc::new(Self { database, path })
+    }
+
+    pub async fn start(self: Arc<Self>) {
+        // The heads also change when `Sync` merges in changes from peers.
+        let mut heads = self.database.get_heads();
+        let mut debounce = Debounce::default();
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let now = Instant::now();
+            let new_heads = self.database.get_heads();
+            if new_heads != heads {
+                heads = new_heads;
+                debounce.changed(now);
+            }
+            if !debounce.due(now) {
+                continue;
+            }
+
+            match self.save().await {
+                Ok(()) => debounce.saved(),
+                Err(e) => {
+                    logging::GLOBAL.error(format!("Failed to autosave: {}", e));
+                    // Try again once the debounce runs out, rather than every poll.
+                    debounce.saved();
+                    debounce.changed(now);
+                }
+            }
+        }
+    }
+
+    pub async fn save(self: &Arc<Self>) -> anyhow::Result<()> {
+        let autosave = self.clone();
+        task::spawn_blocking(move || autosave.database.save_incremental(&autosave.path)).await??;
+        Ok(())
+    }
+}
+
+/// Tracks when the document changed since it was last saved.
+#[derive(Default)]
+struct Debounce {
+    first_change: Option<Instant>,
+    last_change: Option<Instant>,
+}
+
+impl Debounce {
+    fn changed(&mut self, now: Instant) {
+        self.first_change.get_or_insert(now);
+        self.last_change = Some(now);
+    }
+
+    fn saved(&mut self) {
+        self.first_change = None;
+        self.last_change = None;
+    }
+
+    fn due(&self, now: Instant) -> bool {
+        match (self.first_change, self.last_change) {
+            (Some(first_change), Some(last_change)) => {
+                now - last_change >= DEBOUNCE || now - first_change >= MAX_INTERVAL
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debounce() {
+        let start = Instant::now();
+        let mut debounce = Debounce::default();
+        assert!(!debounce.due(start));
+
+        debounce.changed(start);
+        assert!(!debounce.due(start + DEBOUNCE / 2));
+        assert!(debounce.due(start + DEBOUNCE));
+
+        // Changes keep pushing the save back...
+        let mut now = start;
+        while now - start < MAX_INTERVAL {
+            assert!(!debounce.due(now));
+            debounce.changed(now);
+            now += DEBOUNCE / 2;
+        }
+        // ...but only up to the maximum interval.
+        assert!(debounce.due(now));
+
+        debounce.saved();
+        assert!(!debounce.due(now + MAX_INTERVAL));
+    }
+}
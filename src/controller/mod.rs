@@ -1,6 +1,7 @@
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
 use std::net::SocketAddrV4;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::thread;
 
@@ -8,14 +9,18 @@ use automerge::Change;
 use automerge::ChangeHash;
 use automerge::ExpandedChange;
 use lazy_static::lazy_static;
+use tokio::signal::unix::signal;
+use tokio::signal::unix::SignalKind;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
 
+use self::autosave::Autosave;
 use self::registry::Registry;
 use self::sync::Sync;
 use crate::database::Database;
 use crate::logging;
 
+mod autosave;
 mod registry;
 mod sync;
 mod utils;
@@ -30,21 +35,24 @@ pub struct Controller {
 
     registry: Arc<Registry>,
     sync: Arc<Sync>,
+    autosave: Arc<Autosave>,
 
     tx: mpsc::UnboundedSender<Event>,
     rx: Mutex<mpsc::UnboundedReceiver<Event>>,
 }
 
 impl Controller {
-    pub async fn new(database: Arc<Database>) -> anyhow::Result<Arc<Self>> {
+    pub async fn new(database: Arc<Database>, path: PathBuf) -> anyhow::Result<Arc<Self>> {
         let (tx, rx) = mpsc::unbounded_channel();
         let registry = Registry::new();
         let sync = Sync::new(database.clone(), tx.clone());
+        let autosave = Autosave::new(database.clone(), path);
 
         let server = Arc::new(Self {
             database,
             registry,
             sync,
+            autosave,
             tx,
             rx: Mutex::new(rx),
         });
@@ -59,6 +67,11 @@ impl Controller {
             tokio::spawn(sync.start());
         }
 
+        {
+            let autosave = server.autosave.clone();
+            tokio::spawn(autosave.start());
+        }
+
         {
             // This is handled on its own operating system thread
             // because waiting for terminal input is not async.
@@ -67,6 +80,11 @@ impl Controller {
             thread::spawn(|| server.poll_terminal_thread());
         }
 
+        {
+            let server = server.clone();
+            tokio::spawn(server.poll_signals_task());
+        }
+
         Ok(server)
     }
 
@@ -75,6 +93,15 @@ impl Controller {
         rx.recv().await.expect("Failed to poll event.")
     }
 
+    /// Saves and stops peers from trying to sync with us.
+    pub async fn shutdown(self: &Arc<Self>) -> anyhow::Result<()> {
+        let saved = self.autosave.save().await;
+        if let Err(e) = self.sync.deregister().await {
+            logging::GLOBAL.error(format!("Failed to deregister from registry: {}", e));
+        }
+        saved
+    }
+
     fn poll_terminal_thread(self: Arc<Self>) {
         loop {
             if let Err(e) = self.poll_terminal() {
@@ -89,11 +116,33 @@ impl Controller {
         self.tx.send(Event::Terminal(evt))?;
         Ok(())
     }
+
+    async fn poll_signals_task(self: Arc<Self>) {
+        if let Err(e) = self.poll_signals().await {
+            logging::GLOBAL.error(format!("Error while waiting for signals: {}", e));
+        }
+    }
+
+    async fn poll_signals(self: &Arc<Self>) -> anyhow::Result<()> {
+        // In raw mode Ctrl-C arrives as a key press, so these come from e.g. `kill`.
+        let mut sigterm = signal(SignalKind::terminate())?;
+        let mut sighup = signal(SignalKind::hangup())?;
+        let mut sigint = signal(SignalKind::interrupt())?;
+        loop {
+            tokio::select! {
+                _ = sigterm.recv() => {}
+                _ = sighup.recv() => {}
+                _ = sigint.recv() => {}
+            }
+            self.tx.send(Event::Shutdown)?;
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Event {
     Pull,
+    Shutdown,
     Terminal(crossterm::event::Event),
 }
 
@@ -49,6 +49,13 @@ impl Registry {
             .and(warp::body::bytes())
             .then(Self::register_peer);
 
+        let deregister_peer = warp::any()
+            .and(utils::as_context(&self.clone()))
+            .and(warp::path("deregister"))
+            .and(warp::post())
+            .and(warp::body::bytes())
+            .then(Self::deregister_peer);
+
         let get_peers = warp::any()
             .and(utils::as_context(&self.clone()))
             .and(warp::path("peers"))
@@ -58,7 +65,7 @@ impl Registry {
         let filters = warp::any()
             .and(warp::path("api"))
             .and(warp::path("v1"))
-            .and(register_peer.or(get_peers));
+            .and(register_peer.or(deregister_peer).or(get_peers));
 
         // TODO: have this return a Result<...> so that i can recover
         // if there's another registry active on the OS
@@ -66,20 +73,35 @@ impl Registry {
     }
 
     async fn register_peer(self: Arc<Self>, raw_socket_addr: Bytes) -> Response<Body> {
-        let raw_socket_addr = match std::str::from_utf8(&raw_socket_addr) {
-            Err(_) => {
+        let socket_addr = match parse_socket_addr(&raw_socket_addr) {
+            Err(message) => {
                 return Response::builder()
                     .status(400)
-                    .body(Body::from("Provided socket addr is not valid utf8"))
+                    .body(Body::from(message))
                     .unwrap();
             }
-            Ok(raw_socket_addr) => raw_socket_addr,
+            Ok(socket_addr) => socket_addr,
         };
-        let socket_addr = match SocketAddr::from_str(raw_socket_addr) {
-            Err(_) => {
+
+        {
+            let mut peers = self.peers.write().await;
+            if !peers.contains(&socket_addr) {
+                peers.push(socket_addr);
+            }
+        }
+
+        Response::builder()
+            .status(200)
+            .body(Body::from(socket_addr.to_string()))
+            .unwrap()
+    }
+
+    async fn deregister_peer(self: Arc<Self>, raw_socket_addr: Bytes) -> Response<Body> {
+        let socket_addr = match parse_socket_addr(&raw_socket_addr) {
+            Err(message) => {
                 return Response::builder()
                     .status(400)
-                    .body(Body::from(format!("Invalid socket `{}`", raw_socket_addr)))
+                    .body(Body::from(message))
                     .unwrap();
             }
             Ok(socket_addr) => socket_addr,
@@ -87,14 +109,12 @@ impl Registry {
 
         {
             let mut peers = self.peers.write().await;
-            if !peers.contains(&socket_addr) {
-                peers.push(socket_addr);
-            }
+            peers.retain(|peer| *peer != socket_addr);
         }
 
         Response::builder()
             .status(200)
-            .body(Body::from(raw_socket_addr.to_string()))
+            .body(Body::from(socket_addr.to_string()))
             .unwrap()
     }
 
@@ -110,10 +130,39 @@ impl Registry {
     }
 }
 
+fn parse_socket_addr(raw_socket_addr: &[u8]) -> Result<SocketAddr, String> {
+    let raw_socket_addr = std::str::from_utf8(raw_socket_addr)
+        .map_err(|_| "Provided socket addr is not valid utf8".to_string())?;
+    SocketAddr::from_str(raw_socket_addr)
+        .map_err(|_| format!("Invalid socket `{}`", raw_socket_addr))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_deregister_peer() {
+        let registry = Registry::new();
+        let peer = Bytes::from("127.0.0.1:1234");
+        let other_peer = Bytes::from("127.0.0.1:5678");
+
+        let response = Registry::register_peer(registry.clone(), peer.clone()).await;
+        assert_eq!(response.status(), 200);
+        let response = Registry::register_peer(registry.clone(), other_peer).await;
+        assert_eq!(response.status(), 200);
+
+        let response = Registry::deregister_peer(registry.clone(), peer).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            *registry.peers.read().await,
+            vec![SocketAddr::from(([127, 0, 0, 1], 5678))]
+        );
+
+        let response = Registry::deregister_peer(registry.clone(), Bytes::from("nonsense")).await;
+        assert_eq!(response.status(), 400);
+    }
+
     #[tokio::test]
     async fn test_serve_registry() -> anyhow::Result<()> {
         let registry = Registry::new();
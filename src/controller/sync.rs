@@ -9,6 +9,7 @@ use hyper::Response;
 use reqwest::Client;
 use tokio::net::TcpListener;
 use tokio::sync::mpsc;
+use tokio::sync::Mutex;
 use warp::Filter;
 
 use super::deserialize_change_hashes;
@@ -18,16 +19,27 @@ use super::serialize_changes;
 use super::utils;
 use super::Event;
 use crate::database::Database;
+use crate::database::DatabaseError;
+use crate::database::SCHEMA_VERSION;
 use crate::logging;
 
+/// Tells peers which schema version we're on.
+const SCHEMA_VERSION_HEADER: &str = "x-tarsk-schema-version";
+
 pub struct Sync {
     database: Arc<Database>,
     tx: mpsc::UnboundedSender<Event>,
+    /// Where we serve changes from, once we're listening.
+    local_addr: Mutex<Option<SocketAddr>>,
 }
 
 impl Sync {
     pub fn new(database: Arc<Database>, tx: mpsc::UnboundedSender<Event>) -> Arc<Self> {
-        Arc::new(Self { database, tx })
+        Arc::new(Self {
+            database,
+            tx,
+            local_addr: Mutex::new(None),
+        })
     }
 
     pub async fn start(self: Arc<Self>) {
@@ -38,6 +50,7 @@ impl Sync {
         let local_addr = listener
             .local_addr()
             .expect("Failed to get TCP socket address. This shouldn't happen.");
+        *self.local_addr.lock().await = Some(local_addr);
 
         {
             let sync = self.clone();
@@ -53,6 +66,7 @@ impl Sync {
             .and(utils::as_context(&self.clone()))
             .and(warp::path("changes"))
             .and(warp::post())
+            .and(warp::header::optional::<u64>(SCHEMA_VERSION_HEADER))
             .and(warp::body::bytes())
             .then(Self::serve_changes);
 
@@ -62,9 +76,29 @@ impl Sync {
         warp::serve(filters).run_incoming(stream).await
     }
 
-    async fn serve_changes(self: Arc<Self>, raw_change_hashes: Bytes) -> Response<Body> {
+    async fn serve_changes(
+        self: Arc<Self>,
+        schema_version: Option<u64>,
+        raw_change_hashes: Bytes,
+    ) -> Response<Body> {
         // TODO: consider learning how 2 macro to make this better?
         // unwrap Result and then write a custom status / message
+
+        // Peers from before versioning don't send a version at all, so they count as version 0.
+        // That's a hard cutoff: they can't read the migrated layout, so every one of them gets a
+        // 409 until it's upgraded.
+        let schema_version = schema_version.unwrap_or(0);
+        if schema_version < SCHEMA_VERSION {
+            return Response::builder()
+                .status(409)
+                .header(SCHEMA_VERSION_HEADER, SCHEMA_VERSION)
+                .body(Body::from(format!(
+                    "Peer is on schema version {}, but changes are on schema version {}",
+                    schema_version, SCHEMA_VERSION
+                )))
+                .unwrap();
+        }
+
         let change_hashes = match deserialize_change_hashes(&raw_change_hashes) {
             Err(_) => {
                 return Response::builder()
@@ -76,10 +110,14 @@ impl Sync {
         };
 
         let changes = match self.database.get_changes(&change_hashes) {
-            Err(_) => {
+            Err(e) => {
                 return Response::builder()
-                    .status(400)
-                    .body(Body::from("Failed to get changes from Database."))
+                    .status(error_status(&e))
+                    .header(SCHEMA_VERSION_HEADER, SCHEMA_VERSION)
+                    .body(Body::from(format!(
+                        "Failed to get changes from Database: {}",
+                        e
+                    )))
                     .unwrap();
             }
             Ok(changes) => changes,
@@ -88,7 +126,7 @@ impl Sync {
         let raw_changes = match serialize_changes(&changes) {
             Err(_) => {
                 return Response::builder()
-                    .status(400)
+                    .status(500)
                     .body(Body::from("Failed to serialize changes."))
                     .unwrap();
             }
@@ -103,7 +141,11 @@ impl Sync {
                 .unwrap();
         }
 
-        Response::builder().status(200).body(body).unwrap()
+        Response::builder()
+            .status(200)
+            .header(SCHEMA_VERSION_HEADER, SCHEMA_VERSION)
+            .body(body)
+            .unwrap()
     }
 
     async fn query_changes(self: Arc<Self>, local_addr: SocketAddr) {
@@ -155,10 +197,24 @@ impl Sync {
         let changes_url = format!("http://{}/api/v1/changes", peer,);
         let res = client
             .post(changes_url)
+            .header(SCHEMA_VERSION_HEADER, SCHEMA_VERSION)
             .body(raw_change_hashes)
             .send()
             .await?;
 
+        // Saves fetching changes which `Database::apply_changes` would refuse.
+        let peer_version = match res.headers().get(SCHEMA_VERSION_HEADER) {
+            None => 0,
+            Some(version) => version.to_str()?.parse()?,
+        };
+        if peer_version > SCHEMA_VERSION {
+            bail!(
+                "Peer is on schema version {}, but we only support up to {}",
+                peer_version,
+                SCHEMA_VERSION
+            );
+        }
+
         if res.status() != 200 {
             bail!(
                 "Non-200 response from peer ({}): `{}`",
@@ -169,7 +225,8 @@ impl Sync {
 
         let raw_changes = res.bytes().await?;
         let changes = deserialize_changes(&raw_changes)?;
-        self.database.apply_changes(changes)
+        self.database.apply_changes(changes)?;
+        Ok(())
     }
 
     async fn register(self: Arc<Self>, local_addr: SocketAddr) {
@@ -190,4 +247,82 @@ impl Sync {
             tokio::time::sleep(Duration::from_secs(5)).await;
         }
     }
+
+    /// Tells the registry to stop handing us out to peers.
+    pub async fn deregister(self: &Arc<Self>) -> anyhow::Result<()> {
+        let local_addr = match *self.local_addr.lock().await {
+            // We never registered in the first place.
+            None => return Ok(()),
+            Some(local_addr) => local_addr,
+        };
+
+        let registry_url = format!("http://{}/api/v1/deregister", *super::REGISTRY_ADDR);
+        // The registry may be gone already.
+        let res = reqwest::Client::builder()
+            .timeout(Duration::from_secs(2))
+            .build()?
+            .post(&registry_url)
+            .body(local_addr.to_string())
+            .send()
+            .await?;
+        if res.status() != 200 {
+            bail!(
+                "Non-200 response from registry ({}): `{}`",
+                res.status(),
+                res.text().await?
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Picks the HTTP status which tells a peer why we couldn't serve its request.
+fn error_status(e: &DatabaseError) -> u16 {
+    match e {
+        // The peer asked for changes after heads that we don't have.
+        DatabaseError::NotFound(_) => 404,
+        DatabaseError::Invalid(_) => 400,
+        DatabaseError::SchemaMismatch { .. } => 409,
+        DatabaseError::Corrupt(_) => 500,
+        DatabaseError::Io(_) => 503,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn test_error_status() {
+        assert_eq!(error_status(&DatabaseError::NotFound("".to_string())), 404);
+        assert_eq!(error_status(&DatabaseError::Invalid("".to_string())), 400);
+        assert_eq!(
+            error_status(&DatabaseError::SchemaMismatch {
+                version: SCHEMA_VERSION + 1
+            }),
+            409
+        );
+        assert_eq!(error_status(&DatabaseError::Corrupt("".to_string())), 500);
+        assert_eq!(error_status(&DatabaseError::Io(io::Error::other(""))), 503);
+    }
+
+    #[tokio::test]
+    async fn test_serve_changes_schema_version() -> anyhow::Result<()> {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let sync = Sync::new(Arc::new(Database::new()?), tx);
+        let hashes = Bytes::from(serialize_change_hashes(&[])?);
+
+        let response = sync.clone().serve_changes(None, hashes.clone()).await;
+        assert_eq!(response.status(), 409);
+        assert_eq!(
+            response.headers()[SCHEMA_VERSION_HEADER],
+            SCHEMA_VERSION.to_string().as_str()
+        );
+
+        let response = sync.serve_changes(Some(SCHEMA_VERSION), hashes).await;
+        assert_eq!(response.status(), 200);
+        Ok(())
+    }
 }
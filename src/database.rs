@@ -1,57 +1,286 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fs::copy;
 use std::fs::create_dir_all;
+use std::fs::hard_link;
+use std::fs::remove_file;
+use std::fs::rename;
 use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
 use std::io::Read;
 use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Mutex;
 
-use anyhow::anyhow;
 use automerge::transaction::Transactable;
 use automerge::ActorId;
 use automerge::AutoCommit;
+use automerge::AutomergeError;
 use automerge::Change;
 use automerge::ChangeHash;
 use automerge::ObjId;
 use automerge::ObjType;
+use automerge::ScalarValue;
+use automerge::Value;
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::Local;
 use chrono::NaiveDate;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono::Weekday;
+use uuid::Uuid;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+pub type Result<T> = std::result::Result<T, DatabaseError>;
+
+/// Tells apart the actors of the changes made by `deterministic_change`.
+const MIGRATION_CHANGE: u8 = 1;
+const REPAIR_CHANGE: u8 = 2;
+
+/// How every chunk of an automerge document or change starts.
+const CHUNK_MAGIC: [u8; 4] = [0x85, 0x6f, 0x4a, 0x83];
+
+/// The objects every task should have, as created by `Database::add_task`.
+const TASK_OBJECTS: [(&str, ObjType); 8] = [
+    ("title", ObjType::Text),
+    ("body", ObjType::Text),
+    ("tags", ObjType::Map),
+    ("blocked_by", ObjType::Map),
+    ("checklist", ObjType::List),
+    ("comments", ObjType::List),
+    ("fields", ObjType::Map),
+    ("clock", ObjType::List),
+];
+
+/// Bump this and add a migration to `MIGRATIONS` whenever the layout changes.
+pub const SCHEMA_VERSION: u64 = 1;
+
+type Migration = fn(&mut AutoCommit) -> Result<()>;
+
+/// Migration `n` brings a document from version `n` to `n + 1`.
+/// Older peers keep writing after we've migrated, so migrations must be safe to repeat.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [migrate_root_objects];
 
 pub struct Database {
     doc: Mutex<AutoCommit>,
+    /// Held while writing to disk, so that concurrent saves don't interleave.
+    saving: Mutex<()>,
+    /// Unlike the actor id, this stays the same across restarts.
+    device: String,
 }
 
 impl Database {
-    pub fn new() -> anyhow::Result<Self> {
+    pub fn new() -> Result<Self> {
         let mut doc = AutoCommit::new();
         doc.set_actor(ActorId::random());
         doc.put_object(automerge::ROOT, "tasks", ObjType::List)?;
+        doc.put_object(automerge::ROOT, "fields", ObjType::Map)?;
+        doc.put_object(automerge::ROOT, "projects", ObjType::Map)?;
+        doc.put(automerge::ROOT, "schema_version", SCHEMA_VERSION)?;
         Ok(Self {
             doc: Mutex::new(doc),
+            saving: Mutex::new(()),
+            device: ActorId::random().to_hex_string(),
         })
     }
 
-    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+    pub fn with_device(mut self, device: String) -> Self {
+        self.device = device;
+        self
+    }
+
+    /// Reads the device id stored next to `path`, creating it the first time.
+    pub fn device_id<P: AsRef<Path>>(path: P) -> Result<String> {
+        let path = with_suffix(path.as_ref(), "device");
+        let device = String::from_utf8(read_if_exists(&path)?)
+            .map_err(|e| DatabaseError::Corrupt(e.to_string()))?;
+        if !device.trim().is_empty() {
+            return Ok(device.trim().to_string());
+        }
+
+        let device = ActorId::random().to_hex_string();
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        let mut file = File::create(&path)?;
+        file.write_all(device.as_bytes())?;
+        file.sync_all()?;
+        Ok(device)
+    }
+
+    /// Loads the snapshot at `path` and replays the change log next to it.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
         let mut file = File::open(path)?;
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)?;
+        let mut snapshot = Vec::new();
+        file.read_to_end(&mut snapshot)?;
 
-        Self::from_bytes(&contents)
+        let log = read_if_exists(&with_suffix(path, "log"))?;
+        Self::from_snapshot_and_log(&snapshot, &log)
     }
 
-    pub fn save<P: AsRef<Path>>(&self, path: &Path) -> anyhow::Result<()> {
+    /// Callers should hold `saving`.
+    fn write_snapshot(&self, path: &Path) -> Result<()> {
         let parent = path
             .parent()
-            .ok_or_else(|| anyhow!("File doesn't have a parent."))?;
+            .ok_or_else(|| DatabaseError::Invalid("File doesn't have a parent.".to_string()))?;
         create_dir_all(parent)?;
 
-        let mut file = File::create(path)?;
+        // Only swap the new copy in once it's on disk.
+        let temp_path = with_suffix(path, "tmp");
+        let mut file = File::create(&temp_path)?;
         file.write_all(&self.to_bytes())?;
+        file.sync_all()?;
+
+        // Unreadable files are quarantined before we get here.
+        if path.exists() {
+            keep_backup(path)?;
+        }
+        rename(&temp_path, path)?;
+        File::open(parent)?.sync_all()?;
+
+        // The snapshot has everything in the log now.
+        match remove_file(with_suffix(path, "log")) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends new changes to the log, compacting it once it outgrows the snapshot.
+    pub fn save_incremental(&self, path: &Path) -> Result<()> {
+        let _saving = self.saving.lock().unwrap();
+        let snapshot_len = match path.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return self.write_snapshot(path),
+            Err(e) => return Err(e.into()),
+        };
+
+        let changes = {
+            let mut doc = self.doc.lock().unwrap();
+            doc.save_incremental()
+        };
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let log_len = match append_to_log(&with_suffix(path, "log"), &changes) {
+            Ok(log_len) => log_len,
+            // The document now considers these changes saved.
+            Err(_) => return self.write_snapshot(path),
+        };
+        if log_len > snapshot_len {
+            self.write_snapshot(path)?;
+        }
         Ok(())
     }
 
-    fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
-        let doc = AutoCommit::load(bytes)?;
+    /// Loads `path`, or the backup alongside why `path` couldn't be loaded.
+    pub fn load_or_backup<P: AsRef<Path>>(path: P) -> Result<(Self, Option<DatabaseError>)> {
+        let path = path.as_ref();
+        match Self::load(path) {
+            Ok(database) => Ok((database, None)),
+            Err(e @ DatabaseError::SchemaMismatch { .. }) => Err(e),
+            Err(DatabaseError::Io(e)) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            Err(e) => match Self::load(with_suffix(path, "bak")) {
+                Ok(database) => Ok((database, Some(e))),
+                Err(_) => Err(e),
+            },
+        }
+    }
+
+    /// Moves the database at `path` and its log aside under a timestamped name.
+    fn quarantine<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+        let path = path.as_ref();
+        let log_path = with_suffix(path, "log");
+        let timestamp = Local::now().format("%Y%m%d-%H%M%S").to_string();
+        let mut quarantined = with_suffix(path, &format!("corrupt-{}", timestamp));
+        // Don't overwrite a copy quarantined earlier in the same second.
+        let mut attempt = 1;
+        while quarantined.exists() || with_suffix(&quarantined, "log").exists() {
+            quarantined = with_suffix(path, &format!("corrupt-{}-{}", timestamp, attempt));
+            attempt += 1;
+        }
+        if path.exists() {
+            rename(path, &quarantined)?;
+        }
+        if log_path.exists() {
+            rename(&log_path, with_suffix(&quarantined, "log"))?;
+        }
+        Ok(quarantined)
+    }
+
+    /// Quarantines the unreadable database at `path` and replaces it
+    /// with whatever changes can still be read from it and its backup.
+    pub fn recover<P: AsRef<Path>>(path: P) -> Result<(Self, Recovery)> {
+        let path = path.as_ref();
+        let log_path = with_suffix(path, "log");
+        let snapshot = read_if_exists(path)?;
+        let log = read_if_exists(&log_path)?;
+        let backup = read_if_exists(&with_suffix(path, "bak"))?;
+        let quarantined = Self::quarantine(path)?;
+
+        let mut doc = AutoCommit::new();
+        doc.set_actor(ActorId::random());
+        for change in [backup, snapshot, log]
+            .iter()
+            .flat_map(|bytes| salvage_changes(bytes))
+        {
+            // Changes which conflict with ones we have already are skipped.
+            let _ = doc.apply_changes([change]);
+        }
+        let changes = doc.get_changes(&[])?.len();
+        migrate(&mut doc)?;
+
+        let database = Self {
+            doc: Mutex::new(doc),
+            saving: Mutex::new(()),
+            device: ActorId::random().to_hex_string(),
+        };
+        {
+            let _saving = database.saving.lock().unwrap();
+            database.write_snapshot(path)?;
+        }
+        Ok((
+            database,
+            Recovery {
+                quarantined,
+                changes,
+            },
+        ))
+    }
+
+    #[cfg(test)]
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::from_snapshot_and_log(bytes, &[])
+    }
+
+    fn from_snapshot_and_log(snapshot: &[u8], log: &[u8]) -> Result<Self> {
+        let mut doc = AutoCommit::load(snapshot)?;
+        // Automerge skips over blocks it can't recognise, so garbage loads as an empty document.
+        // We never save an empty document.
+        if doc.get_heads().is_empty() {
+            return Err(DatabaseError::Corrupt(
+                "Not an automerge document".to_string(),
+            ));
+        }
+        // A change torn off the end of the log by a crash is skipped the same way.
+        doc.load_incremental(log)?;
+        // Don't write what's on disk already to the log again.
+        doc.save_incremental();
+        migrate(&mut doc)?;
         Ok(Self {
             doc: Mutex::new(doc),
+            saving: Mutex::new(()),
+            device: ActorId::random().to_hex_string(),
         })
     }
 
@@ -65,7 +294,7 @@ impl Database {
         doc.get_heads()
     }
 
-    pub fn get_changes(&self, heads: &[ChangeHash]) -> anyhow::Result<Vec<Change>> {
+    pub fn get_changes(&self, heads: &[ChangeHash]) -> Result<Vec<Change>> {
         let mut doc = self.doc.lock().unwrap();
         let changes = doc
             .get_changes(heads)?
@@ -75,21 +304,60 @@ impl Database {
         Ok(changes)
     }
 
-    pub fn apply_changes<T: IntoIterator<Item = Change>>(&self, changes: T) -> anyhow::Result<()> {
+    /// Merges changes from another peer,
+    /// refusing them as a whole if they bring the document to a newer schema version.
+    pub fn apply_changes<T: IntoIterator<Item = Change>>(&self, changes: T) -> Result<()> {
+        let changes: Vec<Change> = changes.into_iter().collect();
+        if changes.is_empty() {
+            return Ok(());
+        }
+        for change in &changes {
+            let version = written_schema_version(change)?;
+            if version > SCHEMA_VERSION {
+                return Err(DatabaseError::SchemaMismatch { version });
+            }
+        }
+
         let mut doc = self.doc.lock().unwrap();
         doc.apply_changes(changes)?;
-        Ok(())
+        migrate(&mut doc)
     }
 
-    pub fn add_task(&self) -> anyhow::Result<Task<'_>> {
+    pub fn add_task(&self) -> Result<Task<'_>> {
         let mut doc = self.doc.lock().unwrap();
-        let (_, tasks_id) = doc
-            .get(automerge::ROOT, "tasks")?
-            .ok_or_else(|| anyhow!("Missing tasks"))?;
+        let tasks_id = get_tasks_id(&doc)?;
+
+        // New tasks go at the top of the list.
+        let position = get_ordered_tasks(&doc)?
+            .first()
+            .map_or(0.0, |(position, _)| position - 1.0);
 
         let task_obj_id = doc.insert_object(tasks_id, 0, ObjType::Map)?;
+        doc.put(&task_obj_id, "id", Uuid::new_v4().to_string())?;
+        doc.put(&task_obj_id, "position", position)?;
         doc.put_object(&task_obj_id, "title", ObjType::Text)?;
         doc.put_object(&task_obj_id, "body", ObjType::Text)?;
+        doc.put(&task_obj_id, "scheduled", ScalarValue::Null)?;
+        doc.put(&task_obj_id, "deadline", ScalarValue::Null)?;
+        doc.put(&task_obj_id, "recurrence", ScalarValue::Null)?;
+        doc.put(&task_obj_id, "status", Status::Todo.to_string())?;
+        doc.put(&task_obj_id, "completed_at", ScalarValue::Null)?;
+        doc.put(&task_obj_id, "archived", false)?;
+        doc.put(&task_obj_id, "parent", ScalarValue::Null)?;
+        doc.put(&task_obj_id, "project", ScalarValue::Null)?;
+        doc.put_object(&task_obj_id, "tags", ObjType::Map)?;
+        doc.put_object(&task_obj_id, "blocked_by", ObjType::Map)?;
+        doc.put_object(&task_obj_id, "checklist", ObjType::List)?;
+        doc.put_object(&task_obj_id, "comments", ObjType::List)?;
+        doc.put_object(&task_obj_id, "fields", ObjType::Map)?;
+        doc.put_object(&task_obj_id, "clock", ObjType::List)?;
+        doc.put(&task_obj_id, "priority", ScalarValue::Null)?;
+        doc.put(&task_obj_id, "estimate", ScalarValue::Null)?;
+        doc.put(
+            &task_obj_id,
+            "created_at",
+            ScalarValue::Timestamp(Utc::now().timestamp_millis()),
+        )?;
 
         Ok(Task {
             parent: self,
@@ -97,163 +365,4273 @@ impl Database {
         })
     }
 
-    pub fn list_tasks(&self) -> anyhow::Result<Vec<Task<'_>>> {
+    /// Lists every task which hasn't been archived.
+    pub fn list_tasks(&self) -> Result<Vec<Task<'_>>> {
+        self.list_tasks_where(|archived| !archived)
+    }
+
+    pub fn list_tasks_sorted(&self, order: SortOrder) -> Result<Vec<Task<'_>>> {
+        order.sort(self.list_tasks()?)
+    }
+
+    pub fn list_archived_tasks(&self) -> Result<Vec<Task<'_>>> {
+        self.list_tasks_where(|archived| archived)
+    }
+
+    /// Finds a task by its ID, regardless of whether it has been archived.
+    pub fn get_task(&self, id: Uuid) -> Result<Option<Task<'_>>> {
+        let doc = self.doc.lock().unwrap();
+        let tasks_id = get_tasks_id(&doc)?;
+
+        for (_, task_obj_id) in doc.values(tasks_id) {
+            if get_id(&doc, &task_obj_id)? == Some(id) {
+                return Ok(Some(Task {
+                    parent: self,
+                    task_obj_id,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn add_project(&self, name: &str) -> Result<Project<'_>> {
+        let mut doc = self.doc.lock().unwrap();
+        let projects_id = get_object(&doc, &automerge::ROOT, "projects")?;
+
+        // Projects are keyed by ID so that peers creating projects concurrently never collide.
+        let id = Uuid::new_v4().to_string();
+        let project_obj_id = doc.put_object(projects_id, id.as_str(), ObjType::Map)?;
+        doc.put(&project_obj_id, "id", id)?;
+        doc.put(&project_obj_id, "name", name)?;
+        doc.put(&project_obj_id, "description", "")?;
+        doc.put(&project_obj_id, "status", ProjectStatus::Active.to_string())?;
+        doc.put(&project_obj_id, "archived", false)?;
+
+        Ok(Project {
+            parent: self,
+            project_obj_id,
+        })
+    }
+
+    /// Lists every project, including archived ones, sorted by name.
+    pub fn list_projects(&self) -> Result<Vec<Project<'_>>> {
         let doc = self.doc.lock().unwrap();
-        let (_, tasks_id) = doc
-            .get(automerge::ROOT, "tasks")?
-            .ok_or_else(|| anyhow!("Missing tasks"))?;
+        let projects_id = match doc.get(automerge::ROOT, "projects")? {
+            None => return Ok(vec![]),
+            Some((_, projects_id)) => projects_id,
+        };
 
-        let values = doc.values(tasks_id);
-        Ok(values
+        let mut projects = vec![];
+        for (_, project_obj_id) in doc.values(projects_id) {
+            let name = get_string(&doc, &project_obj_id, "name")?.unwrap_or_default();
+            projects.push((name, project_obj_id));
+        }
+        projects.sort();
+        Ok(projects
             .into_iter()
-            .map(|(_, task_obj_id)| Task {
+            .map(|(_, project_obj_id)| Project {
                 parent: self,
-                task_obj_id,
+                project_obj_id,
             })
             .collect())
     }
-}
 
-pub struct Task<'a> {
-    parent: &'a Database,
-    task_obj_id: ObjId,
-}
+    pub fn get_project(&self, id: Uuid) -> Result<Option<Project<'_>>> {
+        let doc = self.doc.lock().unwrap();
+        let projects_id = match doc.get(automerge::ROOT, "projects")? {
+            None => return Ok(None),
+            Some((_, projects_id)) => projects_id,
+        };
+        Ok(doc
+            .get(projects_id, id.to_string())?
+            .map(|(_, project_obj_id)| Project {
+                parent: self,
+                project_obj_id,
+            }))
+    }
 
-impl<'a> Task<'a> {
-    pub fn image(&self) -> anyhow::Result<TaskImage> {
-        let doc = self.parent.doc.lock().unwrap();
-        let (_, title_id) = doc
-            .get(&self.task_obj_id, "title")?
-            .ok_or_else(|| anyhow!("Missing title"))?;
+    /// Reports every task, including archived ones, which can't be read or is missing structure.
+    pub fn diagnose(&self) -> Result<Vec<Diagnostic>> {
+        let task_obj_ids: Vec<ObjId> = {
+            let doc = self.doc.lock().unwrap();
+            doc.values(get_tasks_id(&doc)?)
+                .map(|(_, task_obj_id)| task_obj_id)
+                .collect()
+        };
 
-        let (_, body_id) = doc
-            .get(&self.task_obj_id, "body")?
-            .ok_or_else(|| anyhow!("Missing body"))?;
+        let mut diagnostics = vec![];
+        for (index, task_obj_id) in task_obj_ids.into_iter().enumerate() {
+            let task = Task {
+                parent: self,
+                task_obj_id,
+            };
+            let id = task.id().ok();
+            let mut report = |problem: String, repairable: bool| {
+                diagnostics.push(Diagnostic {
+                    index,
+                    id,
+                    problem,
+                    repairable,
+                })
+            };
 
-        Ok(TaskImage {
-            title: doc.text(title_id)?,
-            scheduled: None,
-            body: doc.text(body_id)?,
-        })
+            {
+                let doc = self.doc.lock().unwrap();
+                match get_id(&doc, &task.task_obj_id) {
+                    Ok(Some(_)) => {}
+                    Ok(None) => report("Missing id".to_string(), true),
+                    Err(e) => report(format!("Malformed id: {}", e), false),
+                }
+                for key in missing_task_objects(&doc, &task.task_obj_id)? {
+                    report(format!("Missing {}", key), true);
+                }
+            }
+            if let Err(e) = task.image() {
+                report(e.to_string(), false);
+            }
+        }
+        Ok(diagnostics)
     }
 
-    pub fn title(&self) -> anyhow::Result<String> {
-        let doc = self.parent.doc.lock().unwrap();
-        let (_, title_id) = doc
-            .get(&self.task_obj_id, "title")?
-            .ok_or_else(|| anyhow!("Missing title"))?;
+    /// Fills in missing structure and returns how many tasks were repaired.
+    /// Malformed values are left alone.
+    pub fn repair(&self) -> Result<usize> {
+        let mut doc = self.doc.lock().unwrap();
+        let mut repaired = 0;
+        deterministic_change(&mut doc, REPAIR_CHANGE, |doc| {
+            let task_obj_ids: Vec<ObjId> = doc
+                .values(get_tasks_id(doc)?)
+                .map(|(_, task_obj_id)| task_obj_id)
+                .collect();
+            for task_obj_id in task_obj_ids {
+                let missing_id = doc.get(&task_obj_id, "id")?.is_none();
+                let missing = missing_task_objects(doc, &task_obj_id)?;
+                if missing_id {
+                    doc.put(&task_obj_id, "id", derive_id(&task_obj_id).to_string())?;
+                }
+                for key in &missing {
+                    let obj_type = TASK_OBJECTS
+                        .iter()
+                        .find(|(candidate, _)| candidate == key)
+                        .map(|(_, obj_type)| *obj_type)
+                        .unwrap();
+                    doc.put_object(&task_obj_id, *key, obj_type)?;
+                }
+                if missing_id || !missing.is_empty() {
+                    repaired += 1;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(repaired)
+    }
 
-        Ok(doc.text(title_id)?)
+    /// Lists the custom fields which tasks can have, sorted by name.
+    pub fn list_fields(&self) -> Result<Vec<(String, FieldType)>> {
+        let doc = self.doc.lock().unwrap();
+        get_field_types(&doc)
     }
 
-    pub fn splice_title<S: AsRef<str>>(
-        &self,
-        pos: usize,
-        delete: usize,
-        contents: S,
-    ) -> anyhow::Result<()> {
-        let mut doc = self.parent.doc.lock().unwrap();
-        let (_, title_id) = doc
-            .get(&self.task_obj_id, "title")?
-            .ok_or_else(|| anyhow!("Missing title"))?;
-        doc.splice_text(title_id, pos, delete, contents.as_ref())?;
+    /// Declares a custom field, or changes the type of an existing one.
+    pub fn define_field(&self, name: &str, field_type: FieldType) -> Result<()> {
+        if name.is_empty() {
+            return Err(DatabaseError::Invalid("Field name is empty".to_string()));
+        }
+        let mut doc = self.doc.lock().unwrap();
+        let fields_id = get_object(&doc, &automerge::ROOT, "fields")?;
+        doc.put(fields_id, name, field_type.to_string())?;
         Ok(())
     }
 
-    pub fn body(&self) -> anyhow::Result<String> {
-        let doc = self.parent.doc.lock().unwrap();
-        let (_, body_id) = doc
-            .get(&self.task_obj_id, "body")?
-            .ok_or_else(|| anyhow!("Missing body"))?;
+    /// Lists every tag used by a task which hasn't been archived, in sorted order.
+    pub fn list_tags(&self) -> Result<Vec<String>> {
+        let mut tags = vec![];
+        for task in self.list_tasks()? {
+            tags.extend(task.tags()?);
+        }
+        tags.sort();
+        tags.dedup();
+        Ok(tags)
+    }
+
+    /// Lists every task carrying `tag` which hasn't been archived.
+    pub fn list_tasks_with_tag(&self, tag: &str) -> Result<Vec<Task<'_>>> {
+        let mut tasks = vec![];
+        for task in self.list_tasks()? {
+            if task.tags()?.iter().any(|task_tag| task_tag == tag) {
+                tasks.push(task);
+            }
+        }
+        Ok(tasks)
+    }
 
-        Ok(doc.text(body_id)?)
+    /// Lists every unfinished task which isn't blocked by another unfinished task.
+    pub fn list_actionable_tasks(&self) -> Result<Vec<Task<'_>>> {
+        let blocked_ids = self.blocked_ids()?;
+        let mut tasks = vec![];
+        for task in self.list_tasks()? {
+            let blocked = task.id().is_ok_and(|id| blocked_ids.contains(&id));
+            if !task.status()?.is_finished() && !blocked {
+                tasks.push(task);
+            }
+        }
+        Ok(tasks)
+    }
+
+    /// Lists every unfinished task which is waiting on another unfinished task.
+    pub fn list_blocked_tasks(&self) -> Result<Vec<Task<'_>>> {
+        let blocked_ids = self.blocked_ids()?;
+        let mut tasks = vec![];
+        for task in self.list_tasks()? {
+            let blocked = task.id().is_ok_and(|id| blocked_ids.contains(&id));
+            if !task.status()?.is_finished() && blocked {
+                tasks.push(task);
+            }
+        }
+        Ok(tasks)
+    }
+
+    /// Returns the IDs of every task with an unfinished blocker.
+    fn blocked_ids(&self) -> Result<HashSet<Uuid>> {
+        let doc = self.doc.lock().unwrap();
+        let mut finished_ids = HashSet::new();
+        for (_, task_obj_id) in get_ordered_tasks(&doc)? {
+            if let Some(id) = get_id(&doc, &task_obj_id)? {
+                if get_status(&doc, &task_obj_id)?.is_finished() {
+                    finished_ids.insert(id);
+                }
+            }
+        }
+        Ok(resolve_dependencies(&doc)?
+            .into_iter()
+            .filter(|(_, blocker_ids)| {
+                blocker_ids
+                    .iter()
+                    .any(|blocker_id| !finished_ids.contains(blocker_id))
+            })
+            .map(|(id, _)| id)
+            .collect())
+    }
+
+    /// Lists every unfinished task whose deadline is before `today`.
+    pub fn list_overdue_tasks(&self, today: NaiveDate) -> Result<Vec<Task<'_>>> {
+        self.list_unfinished_tasks_due(|deadline| deadline < today)
+    }
+
+    /// Lists every unfinished task due between `today` and `days` days later, inclusive.
+    pub fn list_tasks_due_within(&self, today: NaiveDate, days: i64) -> Result<Vec<Task<'_>>> {
+        let last_day = today + Duration::days(days);
+        self.list_unfinished_tasks_due(|deadline| today <= deadline && deadline <= last_day)
     }
 
-    pub fn splice_body<S: AsRef<str>>(
+    fn list_unfinished_tasks_due<F: Fn(NaiveDate) -> bool>(
         &self,
-        pos: usize,
-        delete: usize,
-        contents: S,
-    ) -> anyhow::Result<()> {
-        let mut doc = self.parent.doc.lock().unwrap();
-        let (_, body_id) = doc
-            .get(&self.task_obj_id, "body")?
-            .ok_or_else(|| anyhow!("Missing body"))?;
-        doc.splice_text(body_id, pos, delete, contents.as_ref())?;
-        Ok(())
+        predicate: F,
+    ) -> Result<Vec<Task<'_>>> {
+        let mut tasks = vec![];
+        for task in self.list_tasks()? {
+            if task.status()?.is_finished() {
+                continue;
+            }
+            if let Some(deadline) = task.deadline()? {
+                if predicate(deadline) {
+                    tasks.push(task);
+                }
+            }
+        }
+        Ok(tasks)
+    }
+
+    /// Returns the task this device is clocked into, if any, alongside the running clock entry.
+    pub fn running_clock(&self) -> Result<Option<(Task<'_>, ClockEntry)>> {
+        let doc = self.doc.lock().unwrap();
+        for (_, task_obj_id) in doc.values(get_tasks_id(&doc)?) {
+            for (_, entry) in get_clock_entries(&doc, &task_obj_id)? {
+                if entry.actor == self.device && entry.end.is_none() {
+                    return Ok(Some((
+                        Task {
+                            parent: self,
+                            task_obj_id,
+                        },
+                        entry,
+                    )));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Sums the time clocked on every task, including archived ones, during the local day.
+    pub fn clocked_time_on(&self, day: NaiveDate, now: DateTime<Utc>) -> Result<Duration> {
+        let from = local_midnight(day)?;
+        let to = local_midnight(day + Duration::days(1))?;
+
+        let doc = self.doc.lock().unwrap();
+        let mut total = Duration::zero();
+        for (_, task_obj_id) in doc.values(get_tasks_id(&doc)?) {
+            for (_, entry) in get_clock_entries(&doc, &task_obj_id)? {
+                total = total + entry.overlap(from, to, now);
+            }
+        }
+        Ok(total)
+    }
+
+    /// Compares estimated to actual time per group, counting tasks which have both.
+    pub fn estimate_report(
+        &self,
+        group_by: GroupBy,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<EstimateReport>> {
+        let mut groups: BTreeMap<Option<String>, EstimateReport> = BTreeMap::new();
+        for task in self
+            .list_tasks()?
+            .into_iter()
+            .chain(self.list_archived_tasks()?)
+        {
+            let (estimate, actual) = match (task.estimate()?, task.actual_time(now)?) {
+                (Some(estimate), Some(actual)) => (estimate, actual),
+                _ => continue,
+            };
+
+            let keys = match group_by {
+                GroupBy::Tag => {
+                    let tags = task.tags()?;
+                    if tags.is_empty() {
+                        vec![None]
+                    } else {
+                        tags.into_iter().map(Some).collect()
+                    }
+                }
+                GroupBy::Project => vec![match task.project()? {
+                    None => None,
+                    Some(project) => Some(project.name()?),
+                }],
+            };
+            for key in keys {
+                let report = groups.entry(key.clone()).or_insert_with(|| EstimateReport {
+                    group: key,
+                    tasks: 0,
+                    estimated: Duration::zero(),
+                    actual: Duration::zero(),
+                });
+                report.tasks += 1;
+                report.estimated = report.estimated + estimate;
+                report.actual = report.actual + actual;
+            }
+        }
+        Ok(groups.into_values().collect())
+    }
+
+    /// Lists every task which hasn't been archived depth-first, alongside its depth.
+    pub fn list_task_tree(&self) -> Result<Vec<(usize, Task<'_>)>> {
+        let doc = self.doc.lock().unwrap();
+        let entries: Vec<TaskEntry> = get_task_entries(&doc)?
+            .into_iter()
+            .filter(|entry| !entry.archived)
+            .collect();
+        let ids: HashSet<Uuid> = entries.iter().flat_map(|entry| entry.id).collect();
+
+        let mut roots = vec![];
+        let mut children: HashMap<Uuid, Vec<&TaskEntry>> = HashMap::new();
+        for entry in entries.iter() {
+            match entry.parent {
+                Some(parent) if ids.contains(&parent) => {
+                    children.entry(parent).or_default().push(entry)
+                }
+                _ => roots.push(entry),
+            }
+        }
+
+        let mut tree = vec![];
+        let mut stack: Vec<(usize, &TaskEntry)> =
+            roots.into_iter().rev().map(|entry| (0, entry)).collect();
+        while let Some((depth, entry)) = stack.pop() {
+            tree.push((
+                depth,
+                Task {
+                    parent: self,
+                    task_obj_id: entry.obj_id.clone(),
+                },
+            ));
+            if let Some(children) = entry.id.and_then(|id| children.get(&id)) {
+                stack.extend(children.iter().rev().map(|child| (depth + 1, *child)));
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Moves the task so that it ends up at `new_index` in `list_tasks`.
+    ///
+    /// Re-inserting a task would duplicate it if two peers moved it at the same time,
+    /// so this only writes the task's `position`.
+    pub fn move_task(&self, id: Uuid, new_index: usize) -> Result<()> {
+        let mut doc = self.doc.lock().unwrap();
+        let archived: HashSet<ObjId> = get_task_entries(&doc)?
+            .into_iter()
+            .filter(|entry| entry.archived)
+            .map(|entry| entry.obj_id)
+            .collect();
+        let mut ordered = vec![];
+        let mut task_obj_id = None;
+        for (position, obj_id) in get_ordered_tasks(&doc)? {
+            if get_id(&doc, &obj_id)? == Some(id) {
+                task_obj_id = Some(obj_id);
+            } else if !archived.contains(&obj_id) {
+                ordered.push((position, obj_id));
+            }
+        }
+        let task_obj_id = task_obj_id
+            .ok_or_else(|| DatabaseError::NotFound(format!("No task with id {}", id)))?;
+        move_to_position(&mut doc, ordered, task_obj_id, new_index)
+    }
+
+    fn list_tasks_where<F: Fn(bool) -> bool>(&self, predicate: F) -> Result<Vec<Task<'_>>> {
+        let doc = self.doc.lock().unwrap();
+
+        Ok(get_task_entries(&doc)?
+            .into_iter()
+            .filter(|entry| predicate(entry.archived))
+            .map(|entry| Task {
+                parent: self,
+                task_obj_id: entry.obj_id,
+            })
+            .collect())
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
-pub struct TaskImage {
-    pub title: String,
-    pub scheduled: Option<NaiveDate>,
-    pub body: String,
+struct TaskEntry {
+    obj_id: ObjId,
+    id: Option<Uuid>,
+    parent: Option<Uuid>,
+    archived: bool,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Reads every task in position order, with parents resolved by `resolve_parents`.
+/// Tasks nested under an archived task count as archived.
+fn get_task_entries(doc: &AutoCommit) -> Result<Vec<TaskEntry>> {
+    let mut entries = vec![];
+    for (_, obj_id) in get_ordered_tasks(doc)? {
+        entries.push(TaskEntry {
+            id: get_id(doc, &obj_id)?,
+            parent: get_uuid(doc, &obj_id, "parent")?,
+            archived: get_archived(doc, &obj_id)?,
+            obj_id,
+        });
+    }
+    resolve_parents(&mut entries);
 
-    #[test]
-    fn test_add_task() {
-        let database = Database::new().unwrap();
+    let by_id: HashMap<Uuid, (Option<Uuid>, bool)> = entries
+        .iter()
+        .filter_map(|entry| Some((entry.id?, (entry.parent, entry.archived))))
+        .collect();
+    for entry in entries.iter_mut() {
+        let mut parent = entry.parent;
+        while let Some(parent_id) = parent {
+            if entry.archived {
+                break;
+            }
+            let (grandparent, archived) = by_id[&parent_id];
+            entry.archived = archived;
+            parent = grandparent;
+        }
+    }
+    Ok(entries)
+}
 
-        let task = database.add_task().unwrap();
-        let task_image = task.image().unwrap();
-        assert_eq!(
-            task_image,
-            TaskImage {
-                title: "".to_string(),
-                scheduled: None,
-                body: "".to_string(),
+/// Drops references to missing parents, and breaks cycles made by concurrent moves
+/// by promoting the task with the smallest ID in each.
+fn resolve_parents(entries: &mut [TaskEntry]) {
+    let ids: HashSet<Uuid> = entries.iter().flat_map(|entry| entry.id).collect();
+    let mut parents: HashMap<Uuid, Uuid> = entries
+        .iter()
+        .filter_map(|entry| Some((entry.id?, entry.parent?)))
+        .filter(|(_, parent)| ids.contains(parent))
+        .collect();
+
+    let mut starts: Vec<Uuid> = parents.keys().copied().collect();
+    starts.sort();
+    for start in starts {
+        let mut path = vec![];
+        let mut current = start;
+        loop {
+            if let Some(pos) = path.iter().position(|id| *id == current) {
+                let promoted: Uuid = path[pos..].iter().copied().min().unwrap();
+                parents.remove(&promoted);
+                break;
             }
-        );
+            path.push(current);
+            match parents.get(&current) {
+                None => break,
+                Some(parent) => current = *parent,
+            }
+        }
     }
 
-    #[test]
-    fn test_list_tasks() {
-        let database = Database::new().unwrap();
-        let task = database.add_task().unwrap();
-        task.splice_title(0, 0, "some text").unwrap();
+    for entry in entries.iter_mut() {
+        entry.parent = entry.id.and_then(|id| parents.get(&id).copied());
+    }
+}
 
-        let tasks = database.list_tasks().unwrap();
-        assert_eq!(tasks.len(), 1);
-        let task = &tasks[0];
-        assert_eq!(task.title().unwrap(), "some text");
+/// Lists every task alongside its position, in position order.
+/// Ties from concurrent moves into the same gap are broken by task ID.
+fn get_ordered_tasks(doc: &AutoCommit) -> Result<Vec<(f64, ObjId)>> {
+    let tasks_id = get_tasks_id(doc)?;
+    let mut tasks = vec![];
+    for (index, (_, obj_id)) in doc.values(tasks_id).enumerate() {
+        let position = match doc.get(&obj_id, "position")? {
+            None => index as f64,
+            Some((value, _)) => value
+                .to_f64()
+                .ok_or_else(|| DatabaseError::Corrupt("Position is not a number".to_string()))?,
+        };
+        tasks.push((position, get_id(doc, &obj_id)?, obj_id));
     }
+    tasks.sort_by(|(a_position, a_id, _), (b_position, b_id, _)| {
+        a_position.total_cmp(b_position).then(a_id.cmp(b_id))
+    });
+    Ok(tasks
+        .into_iter()
+        .map(|(position, _, obj_id)| (position, obj_id))
+        .collect())
+}
 
-    #[test]
-    fn test_splice_title() {
-        let database = Database::new().unwrap();
+/// Maps the ID of every task to the IDs of the tasks blocking it.
+/// Cycles made by concurrent additions are broken by walking the graph depth-first
+/// in ID order and dropping each edge back into a task we're still visiting.
+fn resolve_dependencies(doc: &AutoCommit) -> Result<HashMap<Uuid, Vec<Uuid>>> {
+    let mut raw_dependencies = HashMap::new();
+    for (_, obj_id) in get_ordered_tasks(doc)? {
+        if let Some(id) = get_id(doc, &obj_id)? {
+            let mut blocker_ids = vec![];
+            if let Some((_, blocked_by_id)) = doc.get(&obj_id, "blocked_by")? {
+                for key in doc.keys(blocked_by_id) {
+                    blocker_ids.push(
+                        Uuid::parse_str(&key).map_err(|e| DatabaseError::Corrupt(e.to_string()))?,
+                    );
+                }
+            }
+            blocker_ids.sort();
+            raw_dependencies.insert(id, blocker_ids);
+        }
+    }
 
-        let task = database.add_task().unwrap();
-        task.splice_title(0, 0, "hello world!").unwrap();
-        assert_eq!(task.title().unwrap(), "hello world!".to_string());
+    let mut ids: Vec<Uuid> = raw_dependencies.keys().copied().collect();
+    ids.sort();
+
+    // Each task is unvisited (missing), in progress (false), or done (true).
+    let mut visited: HashMap<Uuid, bool> = HashMap::new();
+    let mut dependencies: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for root in ids {
+        if visited.contains_key(&root) {
+            continue;
+        }
+
+        visited.insert(root, false);
+        let mut stack = vec![(root, 0)];
+        while let Some((id, next_edge)) = stack.pop() {
+            match raw_dependencies[&id].get(next_edge) {
+                None => {
+                    visited.insert(id, true);
+                }
+                Some(blocker_id) => {
+                    stack.push((id, next_edge + 1));
+                    if !raw_dependencies.contains_key(blocker_id) {
+                        continue;
+                    }
+                    match visited.get(blocker_id) {
+                        Some(false) => {}
+                        Some(true) => dependencies.entry(id).or_default().push(*blocker_id),
+                        None => {
+                            dependencies.entry(id).or_default().push(*blocker_id);
+                            visited.insert(*blocker_id, false);
+                            stack.push((*blocker_id, 0));
+                        }
+                    }
+                }
+            }
+        }
     }
+    Ok(dependencies)
+}
 
-    #[test]
-    fn test_splice_body() {
-        let database = Database::new().unwrap();
+/// Gives `obj_id` a position which places it at `new_index` among `ordered`.
+fn move_to_position(
+    doc: &mut AutoCommit,
+    mut ordered: Vec<(f64, ObjId)>,
+    obj_id: ObjId,
+    new_index: usize,
+) -> Result<()> {
+    let new_index = new_index.min(ordered.len());
 
-        let task = database.add_task().unwrap();
-        task.splice_body(0, 0, "hello world!").unwrap();
-        assert_eq!(task.body().unwrap(), "hello world!".to_string());
+    let before = new_index.checked_sub(1).map(|i| ordered[i].0);
+    let after = ordered.get(new_index).map(|(position, _)| *position);
+    let position = match (before, after) {
+        (None, None) => 0.0,
+        (Some(before), None) => before + 1.0,
+        (None, Some(after)) => after - 1.0,
+        (Some(before), Some(after)) => before / 2.0 + after / 2.0,
+    };
+
+    // Respace everything once there's no room left between the neighbors.
+    let has_room =
+        before.is_none_or(|before| before < position) && after.is_none_or(|after| position < after);
+    if has_room {
+        doc.put(&obj_id, "position", position)?;
+    } else {
+        ordered.insert(new_index, (position, obj_id));
+        for (i, (_, obj_id)) in ordered.iter().enumerate() {
+            doc.put(obj_id, "position", i as f64)?;
+        }
     }
+    Ok(())
+}
 
-    #[test]
-    fn test_serialization_roundtrip() {
-        let bytes = {
-            let doc = Database::new().unwrap();
-            let task = doc.add_task().unwrap();
-            task.splice_title(0, 0, "hello world").unwrap();
-            doc.to_bytes()
+/// Lists a task's checklist items alongside their positions, in position order.
+fn get_ordered_checklist(doc: &AutoCommit, task_obj_id: &ObjId) -> Result<Vec<(f64, ObjId)>> {
+    let checklist_id = match doc.get(task_obj_id, "checklist")? {
+        None => return Ok(vec![]),
+        Some((_, checklist_id)) => checklist_id,
+    };
+
+    let mut items = vec![];
+    for (index, (_, obj_id)) in doc.values(checklist_id).enumerate() {
+        let position = match doc.get(&obj_id, "position")? {
+            None => index as f64,
+            Some((value, _)) => value
+                .to_f64()
+                .ok_or_else(|| DatabaseError::Corrupt("Position is not a number".to_string()))?,
+        };
+        items.push((position, obj_id));
+    }
+    items.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    Ok(items)
+}
+
+fn get_checklist(doc: &AutoCommit, task_obj_id: &ObjId) -> Result<Vec<ChecklistItem>> {
+    let mut checklist = vec![];
+    for (_, item_id) in get_ordered_checklist(doc, task_obj_id)? {
+        let (_, text_id) = doc
+            .get(&item_id, "text")?
+            .ok_or_else(|| DatabaseError::Corrupt("Missing checklist item text".to_string()))?;
+        let checked = match doc.get(&item_id, "checked")? {
+            None => false,
+            Some((value, _)) => value
+                .to_bool()
+                .ok_or_else(|| DatabaseError::Corrupt("Checked is not a boolean".to_string()))?,
         };
+        checklist.push(ChecklistItem {
+            text: doc.text(text_id)?,
+            checked,
+        });
+    }
+    Ok(checklist)
+}
 
-        let doc = Database::from_bytes(&bytes).unwrap();
-        let tasks = doc.list_tasks().unwrap();
-        assert_eq!(tasks.len(), 1);
-        let task = &tasks[0];
-        assert_eq!(task.title().unwrap(), "hello world");
+fn get_checklist_item(doc: &AutoCommit, task_obj_id: &ObjId, index: usize) -> Result<ObjId> {
+    get_ordered_checklist(doc, task_obj_id)?
+        .into_iter()
+        .nth(index)
+        .map(|(_, item_id)| item_id)
+        .ok_or_else(|| DatabaseError::NotFound(format!("No checklist item at index {}", index)))
+}
+
+fn get_comments(doc: &AutoCommit, task_obj_id: &ObjId) -> Result<Vec<Comment>> {
+    let comments_id = match doc.get(task_obj_id, "comments")? {
+        None => return Ok(vec![]),
+        Some((_, comments_id)) => comments_id,
+    };
+
+    let mut comments = vec![];
+    for (_, comment_id) in doc.values(comments_id) {
+        let author = match doc.get(&comment_id, "author")? {
+            None => return Err(DatabaseError::Corrupt("Missing comment author".to_string())),
+            Some((value, _)) => value.into_string().map_err(|_| {
+                DatabaseError::Corrupt("Comment author is not a string".to_string())
+            })?,
+        };
+        let created_at = get_timestamp(doc, &comment_id, "created_at")?
+            .ok_or_else(|| DatabaseError::Corrupt("Missing comment timestamp".to_string()))?;
+        let text = match doc.get(&comment_id, "text")? {
+            None => return Err(DatabaseError::Corrupt("Missing comment text".to_string())),
+            Some((value, _)) => value
+                .into_string()
+                .map_err(|_| DatabaseError::Corrupt("Comment text is not a string".to_string()))?,
+        };
+        comments.push(Comment {
+            author,
+            created_at,
+            text,
+        });
+    }
+    // Concurrent comments end up in an arbitrary order in the list.
+    comments.sort_by_key(|comment| comment.created_at);
+    Ok(comments)
+}
+
+fn get_clock_entries(doc: &AutoCommit, task_obj_id: &ObjId) -> Result<Vec<(ObjId, ClockEntry)>> {
+    let clock_id = match doc.get(task_obj_id, "clock")? {
+        None => return Ok(vec![]),
+        Some((_, clock_id)) => clock_id,
+    };
+
+    let mut entries = vec![];
+    for (_, entry_id) in doc.values(clock_id) {
+        let entry = ClockEntry {
+            actor: get_string(doc, &entry_id, "actor")?
+                .ok_or_else(|| DatabaseError::Corrupt("Missing clock actor".to_string()))?,
+            start: get_timestamp(doc, &entry_id, "start")?
+                .ok_or_else(|| DatabaseError::Corrupt("Missing clock start".to_string()))?,
+            end: get_timestamp(doc, &entry_id, "end")?,
+        };
+        entries.push((entry_id, entry));
+    }
+    Ok(entries)
+}
+
+/// Returns the moment the given day starts in the local timezone.
+fn local_midnight(day: NaiveDate) -> Result<DateTime<Utc>> {
+    let midnight = Local
+        .from_local_datetime(&day.and_hms(0, 0, 0))
+        .earliest()
+        .ok_or_else(|| DatabaseError::Invalid(format!("{} doesn't have a midnight", day)))?;
+    Ok(midnight.with_timezone(&Utc))
+}
+
+/// Copies the snapshot at `path` to its backup.
+fn keep_backup(path: &Path) -> Result<()> {
+    let backup_path = with_suffix(path, "bak");
+    let temp_path = with_suffix(&backup_path, "tmp");
+    match remove_file(&temp_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    // Linking is free, but not every filesystem supports it.
+    if hard_link(path, &temp_path).is_err() {
+        copy(path, &temp_path)?;
+    }
+    rename(&temp_path, backup_path)?;
+    Ok(())
+}
+
+/// Reads the file at `path`, or nothing if there isn't one.
+fn read_if_exists(path: &Path) -> Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_end(&mut contents)?;
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(contents)
+}
+
+/// Finds every change in `bytes` which can still be decoded.
+fn salvage_changes(bytes: &[u8]) -> Vec<Change> {
+    let starts: Vec<usize> = (0..bytes.len())
+        .filter(|&i| bytes[i..].starts_with(&CHUNK_MAGIC))
+        .collect();
+
+    let mut changes = vec![];
+    let mut salvaged_up_to = 0;
+    for (i, &start) in starts.iter().enumerate() {
+        if start < salvaged_up_to {
+            continue;
+        }
+        // A chunk's contents could happen to look like the start of a chunk.
+        for &end in starts[i + 1..].iter().chain([bytes.len()].iter()) {
+            match Change::load_document(&bytes[start..end]) {
+                Ok(found) if !found.is_empty() => {
+                    changes.extend(found);
+                    salvaged_up_to = end;
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+    changes
+}
+
+/// Appends `changes` to the log at `path`, returning the log's new length.
+fn append_to_log(path: &Path, changes: &[u8]) -> Result<u64> {
+    let mut log = OpenOptions::new().create(true).append(true).open(path)?;
+    log.write_all(changes)?;
+    log.sync_data()?;
+    Ok(log.metadata()?.len())
+}
+
+/// Returns `path` with `.suffix` appended, e.g. `tarsk.db.bak`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Documents from before versioning existed are version 0.
+fn get_schema_version(doc: &AutoCommit) -> Result<u64> {
+    // Peers may have written different versions concurrently.
+    let mut version = 0;
+    for (value, _) in doc.get_all(automerge::ROOT, "schema_version")? {
+        let value = value
+            .to_u64()
+            .ok_or_else(|| DatabaseError::Corrupt("Schema version is not a number".to_string()))?;
+        version = version.max(value);
+    }
+    Ok(version)
+}
+
+/// Returns the highest schema version that `change` writes, or 0 if it doesn't write one.
+fn written_schema_version(change: &Change) -> Result<u64> {
+    let change =
+        serde_json::to_value(change.decode()).map_err(|e| DatabaseError::Corrupt(e.to_string()))?;
+    let ops = change["ops"].as_array().into_iter().flatten();
+    Ok(ops
+        .filter(|op| op["obj"] == "_root" && op["key"] == "schema_version")
+        .filter_map(|op| op["value"].as_u64())
+        .max()
+        .unwrap_or(0))
+}
+
+/// Brings a document up to `SCHEMA_VERSION`, failing if it's newer.
+fn migrate(doc: &mut AutoCommit) -> Result<()> {
+    let version = get_schema_version(doc)?;
+    if version > SCHEMA_VERSION {
+        return Err(DatabaseError::SchemaMismatch { version });
+    }
+    deterministic_change(doc, MIGRATION_CHANGE, |doc| {
+        // Changes from older peers may need migrating even once the version is current.
+        for migration in &MIGRATIONS[..] {
+            migration(doc)?;
+        }
+        if version < SCHEMA_VERSION || doc.get_all(automerge::ROOT, "schema_version")?.len() > 1 {
+            doc.put(automerge::ROOT, "schema_version", SCHEMA_VERSION)?;
+        }
+        Ok(())
+    })
+}
+
+/// Makes `edit` as a change which is identical on every peer making it from the same heads,
+/// so automerge merges them instead of keeping conflicting copies of what they create.
+fn deterministic_change<F>(doc: &mut AutoCommit, purpose: u8, edit: F) -> Result<()>
+where
+    F: FnOnce(&mut AutoCommit) -> Result<()>,
+{
+    let mut actor = [0; 16];
+    actor[0] = purpose;
+    actor[1..9].copy_from_slice(&SCHEMA_VERSION.to_be_bytes());
+    for head in doc.get_heads() {
+        for (byte, head_byte) in actor.iter_mut().zip(head.0) {
+            *byte ^= head_byte;
+        }
+    }
+
+    let session_actor = doc.get_actor().clone();
+    doc.set_actor(ActorId::from(actor));
+    let result = edit(doc);
+    if result.is_err() {
+        doc.rollback();
+    }
+    // Switching actors commits the change.
+    doc.set_actor(session_actor);
+    result
+}
+
+fn derive_id(task_obj_id: &ObjId) -> Uuid {
+    let mut bytes = [0; 16];
+    if let ObjId::Id(counter, actor, _) = task_obj_id {
+        for (byte, actor_byte) in bytes.iter_mut().zip(actor.to_bytes()) {
+            *byte = *actor_byte;
+        }
+        for (byte, counter_byte) in bytes[8..].iter_mut().zip(counter.to_be_bytes()) {
+            *byte ^= counter_byte;
+        }
+    }
+    uuid::Builder::from_random_bytes(bytes).into_uuid()
+}
+
+/// Version 1 creates the root objects which used to be created lazily,
+/// and turns plain string titles and bodies into text.
+fn migrate_root_objects(doc: &mut AutoCommit) -> Result<()> {
+    if doc.get(automerge::ROOT, "tasks")?.is_none() {
+        doc.put_object(automerge::ROOT, "tasks", ObjType::List)?;
+    }
+    if doc.get(automerge::ROOT, "fields")?.is_none() {
+        doc.put_object(automerge::ROOT, "fields", ObjType::Map)?;
+    }
+    if doc.get(automerge::ROOT, "projects")?.is_none() {
+        doc.put_object(automerge::ROOT, "projects", ObjType::Map)?;
+    }
+
+    let task_obj_ids: Vec<ObjId> = doc
+        .values(get_tasks_id(doc)?)
+        .map(|(_, task_obj_id)| task_obj_id)
+        .collect();
+    for task_obj_id in task_obj_ids {
+        for key in ["title", "body"] {
+            if let Some((Value::Scalar(value), _)) = doc.get(&task_obj_id, key)? {
+                if let Some(contents) = value.to_str().map(str::to_string) {
+                    let text_id = doc.put_object(&task_obj_id, key, ObjType::Text)?;
+                    doc.splice_text(&text_id, 0, 0, &contents)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn missing_task_objects(doc: &AutoCommit, task_obj_id: &ObjId) -> Result<Vec<&'static str>> {
+    let mut missing = vec![];
+    for (key, _) in TASK_OBJECTS {
+        if doc.get(task_obj_id, key)?.is_none() {
+            missing.push(key);
+        }
+    }
+    Ok(missing)
+}
+
+fn get_tasks_id(doc: &AutoCommit) -> Result<ObjId> {
+    let (_, tasks_id) = doc
+        .get(automerge::ROOT, "tasks")?
+        .ok_or_else(|| DatabaseError::Corrupt("Missing tasks".to_string()))?;
+    Ok(tasks_id)
+}
+
+pub struct Task<'a> {
+    parent: &'a Database,
+    task_obj_id: ObjId,
+}
+
+impl<'a> Task<'a> {
+    pub fn id(&self) -> Result<Uuid> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_id(&doc, &self.task_obj_id)?
+            .ok_or_else(|| DatabaseError::Corrupt("Missing id".to_string()))
+    }
+
+    pub fn image(&self) -> Result<TaskImage> {
+        let doc = self.parent.doc.lock().unwrap();
+        Ok(TaskImage {
+            title: get_text(&doc, &self.task_obj_id, "title")?,
+            scheduled: get_date(&doc, &self.task_obj_id, "scheduled")?,
+            deadline: get_date(&doc, &self.task_obj_id, "deadline")?,
+            status: get_status(&doc, &self.task_obj_id)?,
+            completed_at: get_timestamp(&doc, &self.task_obj_id, "completed_at")?,
+            priority: get_priority(&doc, &self.task_obj_id)?,
+            recurrence: get_recurrence(&doc, &self.task_obj_id)?,
+            tags: get_tags(&doc, &self.task_obj_id)?,
+            checklist: get_checklist(&doc, &self.task_obj_id)?,
+            body: get_text(&doc, &self.task_obj_id, "body")?,
+        })
+    }
+
+    pub fn title(&self) -> Result<String> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_text(&doc, &self.task_obj_id, "title")
+    }
+
+    pub fn splice_title<S: AsRef<str>>(
+        &self,
+        pos: usize,
+        delete: usize,
+        contents: S,
+    ) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let title_id = get_text_id(&doc, &self.task_obj_id, "title")?;
+        doc.splice_text(title_id, pos, delete, contents.as_ref())?;
+        Ok(())
+    }
+
+    pub fn body(&self) -> Result<String> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_text(&doc, &self.task_obj_id, "body")
+    }
+
+    pub fn splice_body<S: AsRef<str>>(&self, pos: usize, delete: usize, contents: S) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let body_id = get_text_id(&doc, &self.task_obj_id, "body")?;
+        doc.splice_text(body_id, pos, delete, contents.as_ref())?;
+        Ok(())
+    }
+
+    pub fn scheduled(&self) -> Result<Option<NaiveDate>> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_date(&doc, &self.task_obj_id, "scheduled")
+    }
+
+    /// Returns every concurrently written value of `scheduled`, including the winner.
+    /// A cleared date shows up as `None`.
+    pub fn scheduled_conflicts(&self) -> Result<Vec<Option<NaiveDate>>> {
+        let doc = self.parent.doc.lock().unwrap();
+        doc.get_all(&self.task_obj_id, "scheduled")?
+            .into_iter()
+            .map(|(value, _)| parse_date(&value))
+            .collect()
+    }
+
+    pub fn set_scheduled(&self, date: NaiveDate) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        doc.put(
+            &self.task_obj_id,
+            "scheduled",
+            date.format(DATE_FORMAT).to_string(),
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_scheduled(&self) -> Result<()> {
+        // Deleting the key would let a concurrent set always win.
+        let mut doc = self.parent.doc.lock().unwrap();
+        doc.put(&self.task_obj_id, "scheduled", ScalarValue::Null)?;
+        Ok(())
+    }
+
+    pub fn deadline(&self) -> Result<Option<NaiveDate>> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_date(&doc, &self.task_obj_id, "deadline")
+    }
+
+    pub fn set_deadline(&self, date: NaiveDate) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        doc.put(
+            &self.task_obj_id,
+            "deadline",
+            date.format(DATE_FORMAT).to_string(),
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_deadline(&self) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        doc.put(&self.task_obj_id, "deadline", ScalarValue::Null)?;
+        Ok(())
+    }
+
+    pub fn priority(&self) -> Result<Option<Priority>> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_priority(&doc, &self.task_obj_id)
+    }
+
+    pub fn set_priority(&self, priority: Option<Priority>) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let priority = match priority {
+            None => ScalarValue::Null,
+            Some(priority) => ScalarValue::from(priority.to_string()),
+        };
+        doc.put(&self.task_obj_id, "priority", priority)?;
+        Ok(())
+    }
+
+    pub fn estimate(&self) -> Result<Option<Duration>> {
+        let doc = self.parent.doc.lock().unwrap();
+        match doc.get(&self.task_obj_id, "estimate")? {
+            None => Ok(None),
+            Some((Value::Scalar(scalar), _)) => match scalar.as_ref() {
+                ScalarValue::Null => Ok(None),
+                ScalarValue::Int(minutes) => Ok(Some(Duration::minutes(*minutes))),
+                _ => Err(DatabaseError::Corrupt(format!(
+                    "Estimate is not an integer: {}",
+                    scalar
+                ))),
+            },
+            Some((Value::Object(_), _)) => {
+                Err(DatabaseError::Corrupt("Estimate is an object".to_string()))
+            }
+        }
+    }
+
+    /// Sets how long the task is expected to take, to the minute.
+    pub fn set_estimate(&self, estimate: Option<Duration>) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let estimate = match estimate {
+            None => ScalarValue::Null,
+            Some(estimate) => ScalarValue::Int(estimate.num_minutes()),
+        };
+        doc.put(&self.task_obj_id, "estimate", estimate)?;
+        Ok(())
+    }
+
+    /// Returns the time clocked on the task,
+    /// or the time between creating and finishing it if it was never clocked.
+    pub fn actual_time(&self, now: DateTime<Utc>) -> Result<Option<Duration>> {
+        let clocked = self.clocked_time(now)?;
+        if !clocked.is_zero() {
+            return Ok(Some(clocked));
+        }
+
+        let doc = self.parent.doc.lock().unwrap();
+        if !get_status(&doc, &self.task_obj_id)?.is_finished() {
+            return Ok(None);
+        }
+        let created_at = get_timestamp(&doc, &self.task_obj_id, "created_at")?;
+        let completed_at = get_timestamp(&doc, &self.task_obj_id, "completed_at")?;
+        match (created_at, completed_at) {
+            (Some(created_at), Some(completed_at)) => Ok(Some(completed_at - created_at)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn created_at(&self) -> Result<Option<DateTime<Utc>>> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_timestamp(&doc, &self.task_obj_id, "created_at")
+    }
+
+    pub fn status(&self) -> Result<Status> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_status(&doc, &self.task_obj_id)
+    }
+
+    /// Stamps `completed_at` when the task becomes finished and clears it when it's reopened.
+    /// Marking a recurring task as done completes its current occurrence instead.
+    pub fn set_status(&self, status: Status) -> Result<()> {
+        if status == Status::Done && self.recurrence()?.is_some() {
+            return self.complete_occurrence(Local::today().naive_local());
+        }
+
+        let mut doc = self.parent.doc.lock().unwrap();
+        let was_finished = get_status(&doc, &self.task_obj_id)?.is_finished();
+        doc.put(&self.task_obj_id, "status", status.to_string())?;
+        if status.is_finished() != was_finished {
+            let completed_at = if status.is_finished() {
+                ScalarValue::Timestamp(Utc::now().timestamp_millis())
+            } else {
+                ScalarValue::Null
+            };
+            doc.put(&self.task_obj_id, "completed_at", completed_at)?;
+        }
+        Ok(())
+    }
+
+    pub fn recurrence(&self) -> Result<Option<Recurrence>> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_recurrence(&doc, &self.task_obj_id)
+    }
+
+    pub fn set_recurrence(&self, recurrence: Option<Recurrence>) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let recurrence = match recurrence {
+            None => ScalarValue::Null,
+            Some(recurrence) => ScalarValue::from(recurrence.to_string()),
+        };
+        doc.put(&self.task_obj_id, "recurrence", recurrence)?;
+        Ok(())
+    }
+
+    /// Moves a recurring task to its next occurrence and reopens it.
+    /// Peers completing the same occurrence write the same date
+    /// unless it depends on `completed_on`, as for `Recurrence::AfterCompletion`.
+    pub fn complete_occurrence(&self, completed_on: NaiveDate) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let recurrence = get_recurrence(&doc, &self.task_obj_id)?
+            .ok_or_else(|| DatabaseError::Invalid("Task does not recur".to_string()))?;
+
+        let scheduled = get_date(&doc, &self.task_obj_id, "scheduled")?.unwrap_or(completed_on);
+        let next = recurrence.next(scheduled, completed_on);
+        // Older monthly rules would lose their day once it's clamped, so store it now.
+        if recurrence == Recurrence::Monthly(None) {
+            let anchored = Recurrence::Monthly(Some(scheduled.day()));
+            doc.put(&self.task_obj_id, "recurrence", anchored.to_string())?;
+        }
+        doc.put(
+            &self.task_obj_id,
+            "scheduled",
+            next.format(DATE_FORMAT).to_string(),
+        )?;
+
+        if let Some(deadline) = get_date(&doc, &self.task_obj_id, "deadline")? {
+            let deadline = deadline + (next - scheduled);
+            doc.put(
+                &self.task_obj_id,
+                "deadline",
+                deadline.format(DATE_FORMAT).to_string(),
+            )?;
+        }
+
+        doc.put(&self.task_obj_id, "status", Status::Todo.to_string())?;
+        doc.put(&self.task_obj_id, "completed_at", ScalarValue::Null)?;
+        Ok(())
+    }
+
+    /// Returns whether the task, or a task it's nested under, has been archived.
+    pub fn archived(&self) -> Result<bool> {
+        let doc = self.parent.doc.lock().unwrap();
+        Ok(get_task_entries(&doc)?
+            .into_iter()
+            .any(|entry| entry.obj_id == self.task_obj_id && entry.archived))
+    }
+
+    /// Hides the task and its subtasks from `Database::list_tasks`.
+    pub fn archive(&self) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        doc.put(&self.task_obj_id, "archived", true)?;
+        Ok(())
+    }
+
+    /// Unarchives the task along with the tasks it's nested under.
+    pub fn unarchive(&self) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let entries = get_task_entries(&doc)?;
+        let mut obj_id = Some(self.task_obj_id.clone());
+        while let Some(current) = obj_id {
+            if get_archived(&doc, &current)? {
+                doc.put(&current, "archived", false)?;
+            }
+            obj_id = entries
+                .iter()
+                .find(|entry| entry.obj_id == current)
+                .and_then(|entry| entry.parent)
+                .and_then(|parent| entries.iter().find(|entry| entry.id == Some(parent)))
+                .map(|entry| entry.obj_id.clone());
+        }
+        Ok(())
+    }
+
+    pub fn tags(&self) -> Result<Vec<String>> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_tags(&doc, &self.task_obj_id)
+    }
+
+    /// Tags are the keys of a map, so concurrent additions merge and additions win over removals.
+    pub fn add_tag(&self, tag: &str) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let tags_id = get_object(&doc, &self.task_obj_id, "tags")?;
+        doc.put(tags_id, tag, true)?;
+        Ok(())
+    }
+
+    pub fn remove_tag(&self, tag: &str) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        if let Some((_, tags_id)) = doc.get(&self.task_obj_id, "tags")? {
+            if doc.get(&tags_id, tag)?.is_some() {
+                doc.delete(tags_id, tag)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn checklist(&self) -> Result<Vec<ChecklistItem>> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_checklist(&doc, &self.task_obj_id)
+    }
+
+    pub fn add_checklist_item<S: AsRef<str>>(&self, text: S) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let position = get_ordered_checklist(&doc, &self.task_obj_id)?
+            .last()
+            .map_or(0.0, |(position, _)| position + 1.0);
+
+        let checklist_id = get_object(&doc, &self.task_obj_id, "checklist")?;
+        let length = doc.length(&checklist_id);
+        let item_id = doc.insert_object(checklist_id, length, ObjType::Map)?;
+        let text_id = doc.put_object(&item_id, "text", ObjType::Text)?;
+        doc.splice_text(text_id, 0, 0, text.as_ref())?;
+        doc.put(&item_id, "checked", false)?;
+        doc.put(&item_id, "position", position)?;
+        Ok(())
+    }
+
+    pub fn splice_checklist_item<S: AsRef<str>>(
+        &self,
+        index: usize,
+        pos: usize,
+        delete: usize,
+        contents: S,
+    ) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let item_id = get_checklist_item(&doc, &self.task_obj_id, index)?;
+        let (_, text_id) = doc
+            .get(&item_id, "text")?
+            .ok_or_else(|| DatabaseError::Corrupt("Missing checklist item text".to_string()))?;
+        doc.splice_text(text_id, pos, delete, contents.as_ref())?;
+        Ok(())
+    }
+
+    pub fn toggle_checklist_item(&self, index: usize) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let item_id = get_checklist_item(&doc, &self.task_obj_id, index)?;
+        let checked = match doc.get(&item_id, "checked")? {
+            None => false,
+            Some((value, _)) => value.to_bool().unwrap_or(false),
+        };
+        doc.put(&item_id, "checked", !checked)?;
+        Ok(())
+    }
+
+    /// Like `Database::move_task`, this only rewrites the item's position.
+    pub fn move_checklist_item(&self, index: usize, new_index: usize) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let mut ordered = get_ordered_checklist(&doc, &self.task_obj_id)?;
+        if index >= ordered.len() {
+            return Err(DatabaseError::NotFound(format!(
+                "No checklist item at index {}",
+                index
+            )));
+        }
+        let (_, item_id) = ordered.remove(index);
+        move_to_position(&mut doc, ordered, item_id, new_index)
+    }
+
+    pub fn remove_checklist_item(&self, index: usize) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let item_id = get_checklist_item(&doc, &self.task_obj_id, index)?;
+        let (_, checklist_id) = doc
+            .get(&self.task_obj_id, "checklist")?
+            .ok_or_else(|| DatabaseError::Corrupt("Missing checklist".to_string()))?;
+        let list_index = doc
+            .values(&checklist_id)
+            .position(|(_, obj_id)| obj_id == item_id)
+            .ok_or_else(|| {
+                DatabaseError::NotFound("Checklist item has already been removed".to_string())
+            })?;
+        doc.delete(checklist_id, list_index)?;
+        Ok(())
+    }
+
+    /// Lists the comments on the task, oldest first.
+    pub fn comments(&self) -> Result<Vec<Comment>> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_comments(&doc, &self.task_obj_id)
+    }
+
+    /// Appends a comment authored by this device.
+    pub fn add_comment(&self, text: &str) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let comments_id = get_object(&doc, &self.task_obj_id, "comments")?;
+        let length = doc.length(&comments_id);
+        let comment_id = doc.insert_object(comments_id, length, ObjType::Map)?;
+        doc.put(&comment_id, "author", self.parent.device.as_str())?;
+        doc.put(
+            &comment_id,
+            "created_at",
+            ScalarValue::Timestamp(Utc::now().timestamp_millis()),
+        )?;
+        doc.put(&comment_id, "text", text)?;
+        Ok(())
+    }
+
+    pub fn clock_entries(&self) -> Result<Vec<ClockEntry>> {
+        let doc = self.parent.doc.lock().unwrap();
+        Ok(get_clock_entries(&doc, &self.task_obj_id)?
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect())
+    }
+
+    /// Sums the time clocked on the task by every peer, counting running clocks up to `now`.
+    pub fn clocked_time(&self, now: DateTime<Utc>) -> Result<Duration> {
+        Ok(self
+            .clock_entries()?
+            .iter()
+            .fold(Duration::zero(), |total, entry| total + entry.duration(now)))
+    }
+
+    pub fn clock_in(&self) -> Result<()> {
+        self.clock_in_at(Utc::now())
+    }
+
+    /// Starts a clock on the task, stopping any clock this device has running on another task.
+    pub fn clock_in_at(&self, time: DateTime<Utc>) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let actor = &self.parent.device;
+
+        let tasks_id = get_tasks_id(&doc)?;
+        let task_obj_ids: Vec<ObjId> = doc.values(tasks_id).map(|(_, obj_id)| obj_id).collect();
+        for task_obj_id in task_obj_ids {
+            for (entry_id, entry) in get_clock_entries(&doc, &task_obj_id)? {
+                if entry.actor != *actor || entry.end.is_some() {
+                    continue;
+                }
+                if task_obj_id == self.task_obj_id {
+                    // We're already clocked into this task.
+                    return Ok(());
+                }
+                doc.put(
+                    entry_id,
+                    "end",
+                    ScalarValue::Timestamp(time.timestamp_millis()),
+                )?;
+            }
+        }
+
+        let clock_id = get_object(&doc, &self.task_obj_id, "clock")?;
+        let length = doc.length(&clock_id);
+        let entry_id = doc.insert_object(clock_id, length, ObjType::Map)?;
+        doc.put(&entry_id, "actor", actor.as_str())?;
+        doc.put(
+            &entry_id,
+            "start",
+            ScalarValue::Timestamp(time.timestamp_millis()),
+        )?;
+        doc.put(&entry_id, "end", ScalarValue::Null)?;
+        Ok(())
+    }
+
+    pub fn clock_out(&self) -> Result<()> {
+        self.clock_out_at(Utc::now())
+    }
+
+    /// Stops the clock this device has running on the task.
+    pub fn clock_out_at(&self, time: DateTime<Utc>) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let (entry_id, _) = get_clock_entries(&doc, &self.task_obj_id)?
+            .into_iter()
+            .find(|(_, entry)| entry.actor == self.parent.device && entry.end.is_none())
+            .ok_or_else(|| DatabaseError::NotFound("Not clocked into this task".to_string()))?;
+        doc.put(
+            entry_id,
+            "end",
+            ScalarValue::Timestamp(time.timestamp_millis()),
+        )?;
+        Ok(())
+    }
+
+    /// A value which doesn't match the field's current type reads as unset.
+    pub fn get_field(&self, name: &str) -> Result<Option<FieldValue>> {
+        let doc = self.parent.doc.lock().unwrap();
+        let field_type = get_field_type(&doc, name)?
+            .ok_or_else(|| DatabaseError::NotFound(format!("Unknown field `{}`", name)))?;
+        get_field_value(&doc, &self.task_obj_id, name, &field_type)
+    }
+
+    /// Sets a custom field after checking the value against the schema.
+    pub fn set_field(&self, name: &str, value: FieldValue) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let field_type = get_field_type(&doc, name)?
+            .ok_or_else(|| DatabaseError::NotFound(format!("Unknown field `{}`", name)))?;
+        field_type.validate(&value)?;
+
+        let fields_id = get_object(&doc, &self.task_obj_id, "fields")?;
+        doc.put(fields_id, name, value.to_scalar())?;
+        Ok(())
+    }
+
+    pub fn clear_field(&self, name: &str) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        if let Some((_, fields_id)) = doc.get(&self.task_obj_id, "fields")? {
+            doc.put(fields_id, name, ScalarValue::Null)?;
+        }
+        Ok(())
+    }
+
+    /// Lists the tasks blocking this one, leaving out any which form a cycle.
+    pub fn blockers(&self) -> Result<Vec<Task<'a>>> {
+        let doc = self.parent.doc.lock().unwrap();
+        let id = match get_id(&doc, &self.task_obj_id)? {
+            None => return Ok(vec![]),
+            Some(id) => id,
+        };
+
+        let dependencies = resolve_dependencies(&doc)?;
+        let blocker_ids = match dependencies.get(&id) {
+            None => return Ok(vec![]),
+            Some(blocker_ids) => blocker_ids,
+        };
+
+        let mut blockers = vec![];
+        for (_, task_obj_id) in get_ordered_tasks(&doc)? {
+            if let Some(blocker_id) = get_id(&doc, &task_obj_id)? {
+                if blocker_ids.contains(&blocker_id) {
+                    blockers.push(Task {
+                        parent: self.parent,
+                        task_obj_id,
+                    });
+                }
+            }
+        }
+        Ok(blockers)
+    }
+
+    /// Returns whether any of the task's blockers are unfinished.
+    pub fn is_blocked(&self) -> Result<bool> {
+        for blocker in self.blockers()? {
+            if !blocker.status()?.is_finished() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Fails if `blocker` already depends on this task.
+    pub fn add_blocker(&self, blocker: &Task) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let id = get_id(&doc, &self.task_obj_id)?
+            .ok_or_else(|| DatabaseError::Corrupt("Missing id".to_string()))?;
+        let blocker_id = get_id(&doc, &blocker.task_obj_id)?
+            .ok_or_else(|| DatabaseError::Corrupt("Missing blocker id".to_string()))?;
+
+        let dependencies = resolve_dependencies(&doc)?;
+        let mut stack = vec![blocker_id];
+        let mut visited = HashSet::new();
+        while let Some(current) = stack.pop() {
+            if current == id {
+                return Err(DatabaseError::Invalid(
+                    "Task cannot be blocked by one of its dependents".to_string(),
+                ));
+            }
+            if visited.insert(current) {
+                stack.extend(dependencies.get(&current).into_iter().flatten());
+            }
+        }
+
+        let blocked_by_id = get_object(&doc, &self.task_obj_id, "blocked_by")?;
+        doc.put(blocked_by_id, blocker_id.to_string(), true)?;
+        Ok(())
+    }
+
+    /// Removes every blocker, including those left out of `blockers`.
+    pub fn clear_blockers(&self) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        if let Some((_, blocked_by_id)) = doc.get(&self.task_obj_id, "blocked_by")? {
+            let keys: Vec<String> = doc.keys(&blocked_by_id).collect();
+            for key in keys {
+                doc.delete(&blocked_by_id, key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the task this task is nested under, if any.
+    pub fn parent_task(&self) -> Result<Option<Task<'a>>> {
+        let doc = self.parent.doc.lock().unwrap();
+        let entries = get_task_entries(&doc)?;
+        let parent = entries
+            .iter()
+            .find(|entry| entry.obj_id == self.task_obj_id)
+            .and_then(|entry| entry.parent);
+
+        Ok(entries
+            .into_iter()
+            .find(|entry| parent.is_some() && entry.id == parent)
+            .map(|entry| Task {
+                parent: self.parent,
+                task_obj_id: entry.obj_id,
+            }))
+    }
+
+    /// Lists the tasks nested directly under this one which haven't been archived.
+    pub fn subtasks(&self) -> Result<Vec<Task<'a>>> {
+        let doc = self.parent.doc.lock().unwrap();
+        let id = get_id(&doc, &self.task_obj_id)?;
+        if id.is_none() {
+            return Ok(vec![]);
+        }
+
+        Ok(get_task_entries(&doc)?
+            .into_iter()
+            .filter(|entry| !entry.archived && entry.parent == id)
+            .map(|entry| Task {
+                parent: self.parent,
+                task_obj_id: entry.obj_id,
+            })
+            .collect())
+    }
+
+    pub fn project(&self) -> Result<Option<Project<'a>>> {
+        let project_id = {
+            let doc = self.parent.doc.lock().unwrap();
+            get_uuid(&doc, &self.task_obj_id, "project")?
+        };
+        match project_id {
+            None => Ok(None),
+            Some(project_id) => self.parent.get_project(project_id),
+        }
+    }
+
+    pub fn set_project(&self, project: Option<&Project>) -> Result<()> {
+        let project_id = match project {
+            None => None,
+            Some(project) => Some(project.id()?),
+        };
+        let mut doc = self.parent.doc.lock().unwrap();
+        match project_id {
+            None => doc.put(&self.task_obj_id, "project", ScalarValue::Null)?,
+            Some(project_id) => doc.put(&self.task_obj_id, "project", project_id.to_string())?,
+        }
+        Ok(())
+    }
+
+    /// Adds a task nested under this one, in the same project.
+    pub fn add_subtask(&self) -> Result<Task<'a>> {
+        let subtask = self.parent.add_task()?;
+        subtask.move_to(Some(self))?;
+        subtask.set_project(self.project()?.as_ref())?;
+        Ok(subtask)
+    }
+
+    /// Nests this task under `new_parent`, or at the top level for `None`.
+    pub fn move_to(&self, new_parent: Option<&Task>) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let id = get_id(&doc, &self.task_obj_id)?
+            .ok_or_else(|| DatabaseError::Corrupt("Missing id".to_string()))?;
+
+        let new_parent_id = match new_parent {
+            None => {
+                doc.put(&self.task_obj_id, "parent", ScalarValue::Null)?;
+                return Ok(());
+            }
+            Some(new_parent) => get_id(&doc, &new_parent.task_obj_id)?
+                .ok_or_else(|| DatabaseError::Corrupt("Missing parent id".to_string()))?,
+        };
+
+        let parents: HashMap<Uuid, Uuid> = get_task_entries(&doc)?
+            .into_iter()
+            .filter_map(|entry| Some((entry.id?, entry.parent?)))
+            .collect();
+        let mut ancestor = Some(new_parent_id);
+        while let Some(current) = ancestor {
+            if current == id {
+                return Err(DatabaseError::Invalid(
+                    "Cannot move a task underneath itself".to_string(),
+                ));
+            }
+            ancestor = parents.get(&current).copied();
+        }
+
+        doc.put(&self.task_obj_id, "parent", new_parent_id.to_string())?;
+        Ok(())
+    }
+
+    /// Deletion wins over concurrent edits, which land on an unreachable object.
+    pub fn delete(self) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        let tasks_id = get_tasks_id(&doc)?;
+        let index = doc
+            .values(&tasks_id)
+            .position(|(_, task_obj_id)| task_obj_id == self.task_obj_id)
+            .ok_or_else(|| DatabaseError::NotFound("Task has already been deleted".to_string()))?;
+        doc.delete(&tasks_id, index)?;
+        Ok(())
+    }
+}
+
+/// A group of related tasks, which refer to it by ID.
+pub struct Project<'a> {
+    parent: &'a Database,
+    project_obj_id: ObjId,
+}
+
+impl<'a> Project<'a> {
+    pub fn id(&self) -> Result<Uuid> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_id(&doc, &self.project_obj_id)?
+            .ok_or_else(|| DatabaseError::Corrupt("Missing id".to_string()))
+    }
+
+    pub fn name(&self) -> Result<String> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_string(&doc, &self.project_obj_id, "name")?
+            .ok_or_else(|| DatabaseError::Corrupt("Missing name".to_string()))
+    }
+
+    pub fn description(&self) -> Result<String> {
+        let doc = self.parent.doc.lock().unwrap();
+        Ok(get_string(&doc, &self.project_obj_id, "description")?.unwrap_or_default())
+    }
+
+    pub fn set_description(&self, description: &str) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        doc.put(&self.project_obj_id, "description", description)?;
+        Ok(())
+    }
+
+    pub fn status(&self) -> Result<ProjectStatus> {
+        let doc = self.parent.doc.lock().unwrap();
+        match get_string(&doc, &self.project_obj_id, "status")? {
+            None => Ok(ProjectStatus::Active),
+            Some(status) => parse_stored(&status),
+        }
+    }
+
+    pub fn set_status(&self, status: ProjectStatus) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        doc.put(&self.project_obj_id, "status", status.to_string())?;
+        Ok(())
+    }
+
+    pub fn archived(&self) -> Result<bool> {
+        let doc = self.parent.doc.lock().unwrap();
+        get_archived(&doc, &self.project_obj_id)
+    }
+
+    /// Archives the project, leaving its tasks as they are.
+    pub fn archive(&self) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        doc.put(&self.project_obj_id, "archived", true)?;
+        Ok(())
+    }
+
+    pub fn unarchive(&self) -> Result<()> {
+        let mut doc = self.parent.doc.lock().unwrap();
+        doc.put(&self.project_obj_id, "archived", false)?;
+        Ok(())
+    }
+
+    /// Sums the time clocked on the project's tasks which haven't been archived.
+    pub fn clocked_time(&self, now: DateTime<Utc>) -> Result<Duration> {
+        let mut total = Duration::zero();
+        for task in self.tasks()? {
+            total = total + task.clocked_time(now)?;
+        }
+        Ok(total)
+    }
+
+    /// Lists the project's tasks which haven't been archived, in position order.
+    pub fn tasks(&self) -> Result<Vec<Task<'a>>> {
+        let id = self.id()?;
+        let mut tasks = vec![];
+        for task in self.parent.list_tasks()? {
+            let doc = self.parent.doc.lock().unwrap();
+            if get_uuid(&doc, &task.task_obj_id, "project")? == Some(id) {
+                tasks.push(task);
+            }
+        }
+        Ok(tasks)
+    }
+}
+
+fn get_text(doc: &AutoCommit, obj_id: &ObjId, key: &str) -> Result<String> {
+    match doc.get(obj_id, key)? {
+        None => Ok(String::new()),
+        Some((Value::Object(ObjType::Text), text_id)) => Ok(doc.text(text_id)?),
+        Some((value, _)) => value
+            .into_string()
+            .map_err(|value| DatabaseError::Corrupt(format!("{} is not text: {}", key, value))),
+    }
+}
+
+fn get_text_id(doc: &AutoCommit, obj_id: &ObjId, key: &str) -> Result<ObjId> {
+    match doc.get(obj_id, key)? {
+        Some((Value::Object(ObjType::Text), text_id)) => Ok(text_id),
+        Some((value, _)) => Err(DatabaseError::Corrupt(format!(
+            "{} is not text: {}",
+            key, value
+        ))),
+        None => Err(DatabaseError::Corrupt(format!("Missing {}", key))),
+    }
+}
+
+/// Missing objects are only created by `migrate` and `Database::repair`,
+/// since peers creating the same object concurrently end up with two.
+fn get_object(doc: &AutoCommit, obj_id: &ObjId, key: &str) -> Result<ObjId> {
+    let (_, child_id) = doc
+        .get(obj_id, key)?
+        .ok_or_else(|| DatabaseError::Corrupt(format!("Missing {}", key)))?;
+    Ok(child_id)
+}
+
+fn get_tags(doc: &AutoCommit, obj_id: &ObjId) -> Result<Vec<String>> {
+    // Map keys are already returned in sorted order.
+    match doc.get(obj_id, "tags")? {
+        None => Ok(vec![]),
+        Some((_, tags_id)) => Ok(doc.keys(tags_id).collect()),
+    }
+}
+
+fn get_id(doc: &AutoCommit, obj_id: &ObjId) -> Result<Option<Uuid>> {
+    get_uuid(doc, obj_id, "id")
+}
+
+fn get_uuid(doc: &AutoCommit, obj_id: &ObjId, key: &str) -> Result<Option<Uuid>> {
+    match doc.get(obj_id, key)? {
+        None => Ok(None),
+        Some((Value::Scalar(scalar), _)) => match scalar.as_ref() {
+            ScalarValue::Null => Ok(None),
+            ScalarValue::Str(id) => Ok(Some(
+                Uuid::parse_str(id).map_err(|e| DatabaseError::Corrupt(e.to_string()))?,
+            )),
+            _ => Err(DatabaseError::Corrupt(format!(
+                "Id is not a string: {}",
+                scalar
+            ))),
+        },
+        Some((Value::Object(_), _)) => Err(DatabaseError::Corrupt("Id is an object".to_string())),
+    }
+}
+
+fn get_string(doc: &AutoCommit, obj_id: &ObjId, key: &str) -> Result<Option<String>> {
+    match doc.get(obj_id, key)? {
+        None => Ok(None),
+        Some((value, _)) => {
+            Ok(Some(value.into_string().map_err(|_| {
+                DatabaseError::Corrupt(format!("{} is not a string", key))
+            })?))
+        }
+    }
+}
+
+fn get_archived(doc: &AutoCommit, obj_id: &ObjId) -> Result<bool> {
+    match doc.get(obj_id, "archived")? {
+        None => Ok(false),
+        Some((value, _)) => value
+            .to_bool()
+            .ok_or_else(|| DatabaseError::Corrupt("Archived is not a boolean".to_string())),
+    }
+}
+
+fn get_priority(doc: &AutoCommit, obj_id: &ObjId) -> Result<Option<Priority>> {
+    match doc.get(obj_id, "priority")? {
+        None => Ok(None),
+        Some((Value::Scalar(scalar), _)) => match scalar.as_ref() {
+            ScalarValue::Null => Ok(None),
+            ScalarValue::Str(priority) => Ok(Some(parse_stored(priority)?)),
+            _ => Err(DatabaseError::Corrupt(format!(
+                "Priority is not a string: {}",
+                scalar
+            ))),
+        },
+        Some((Value::Object(_), _)) => {
+            Err(DatabaseError::Corrupt("Priority is an object".to_string()))
+        }
+    }
+}
+
+fn get_recurrence(doc: &AutoCommit, obj_id: &ObjId) -> Result<Option<Recurrence>> {
+    match doc.get(obj_id, "recurrence")? {
+        None => Ok(None),
+        Some((Value::Scalar(scalar), _)) => match scalar.as_ref() {
+            ScalarValue::Null => Ok(None),
+            ScalarValue::Str(recurrence) => Ok(Some(parse_stored(recurrence)?)),
+            _ => Err(DatabaseError::Corrupt(format!(
+                "Recurrence is not a string: {}",
+                scalar
+            ))),
+        },
+        Some((Value::Object(_), _)) => Err(DatabaseError::Corrupt(
+            "Recurrence is an object".to_string(),
+        )),
+    }
+}
+
+fn get_status(doc: &AutoCommit, obj_id: &ObjId) -> Result<Status> {
+    // Tasks created before statuses existed are treated as not yet started.
+    match doc.get(obj_id, "status")? {
+        None => Ok(Status::Todo),
+        Some((value, _)) => {
+            let status = value
+                .to_str()
+                .ok_or_else(|| DatabaseError::Corrupt("Status is not a string".to_string()))?;
+            parse_stored(status)
+        }
+    }
+}
+
+fn get_field_types(doc: &AutoCommit) -> Result<Vec<(String, FieldType)>> {
+    let fields_id = match doc.get(automerge::ROOT, "fields")? {
+        None => return Ok(vec![]),
+        Some((_, fields_id)) => fields_id,
+    };
+    let mut fields = vec![];
+    for name in doc.keys(&fields_id) {
+        if let Some(field_type) = get_field_type(doc, &name)? {
+            fields.push((name, field_type));
+        }
+    }
+    Ok(fields)
+}
+
+fn get_field_type(doc: &AutoCommit, name: &str) -> Result<Option<FieldType>> {
+    let fields_id = match doc.get(automerge::ROOT, "fields")? {
+        None => return Ok(None),
+        Some((_, fields_id)) => fields_id,
+    };
+    match doc.get(fields_id, name)? {
+        None => Ok(None),
+        Some((value, _)) => {
+            let field_type = value
+                .to_str()
+                .ok_or_else(|| DatabaseError::Corrupt("Field type is not a string".to_string()))?;
+            Ok(Some(parse_stored(field_type)?))
+        }
+    }
+}
+
+fn get_field_value(
+    doc: &AutoCommit,
+    task_obj_id: &ObjId,
+    name: &str,
+    field_type: &FieldType,
+) -> Result<Option<FieldValue>> {
+    let fields_id = match doc.get(task_obj_id, "fields")? {
+        None => return Ok(None),
+        Some((_, fields_id)) => fields_id,
+    };
+    let scalar = match doc.get(fields_id, name)? {
+        Some((Value::Scalar(scalar), _)) => scalar,
+        _ => return Ok(None),
+    };
+    let value = match (field_type, scalar.as_ref()) {
+        (FieldType::String, ScalarValue::Str(s)) => FieldValue::String(s.to_string()),
+        (FieldType::Number, ScalarValue::F64(n)) => FieldValue::Number(*n),
+        (FieldType::Date, ScalarValue::Str(date)) => {
+            match NaiveDate::parse_from_str(date, DATE_FORMAT) {
+                Ok(date) => FieldValue::Date(date),
+                Err(_) => return Ok(None),
+            }
+        }
+        (FieldType::Enum(_), ScalarValue::Str(option)) => FieldValue::Enum(option.to_string()),
+        (FieldType::Boolean, ScalarValue::Boolean(b)) => FieldValue::Boolean(*b),
+        _ => return Ok(None),
+    };
+    if field_type.validate(&value).is_err() {
+        return Ok(None);
+    }
+    Ok(Some(value))
+}
+
+fn get_timestamp(doc: &AutoCommit, obj_id: &ObjId, key: &str) -> Result<Option<DateTime<Utc>>> {
+    match doc.get(obj_id, key)? {
+        None => Ok(None),
+        Some((Value::Scalar(scalar), _)) => match scalar.as_ref() {
+            ScalarValue::Null => Ok(None),
+            ScalarValue::Timestamp(millis) => Ok(Some(
+                Utc.timestamp_millis_opt(*millis).single().ok_or_else(|| {
+                    DatabaseError::Corrupt(format!("Invalid timestamp: {}", millis))
+                })?,
+            )),
+            _ => Err(DatabaseError::Corrupt(format!(
+                "Timestamp is not a timestamp: {}",
+                scalar
+            ))),
+        },
+        Some((Value::Object(_), _)) => {
+            Err(DatabaseError::Corrupt("Timestamp is an object".to_string()))
+        }
+    }
+}
+
+fn get_date(doc: &AutoCommit, obj_id: &ObjId, key: &str) -> Result<Option<NaiveDate>> {
+    match doc.get(obj_id, key)? {
+        None => Ok(None),
+        Some((value, _)) => parse_date(&value),
+    }
+}
+
+fn parse_date(value: &Value) -> Result<Option<NaiveDate>> {
+    match value {
+        Value::Scalar(scalar) => match scalar.as_ref() {
+            ScalarValue::Null => Ok(None),
+            ScalarValue::Str(date) => Ok(Some(
+                NaiveDate::parse_from_str(date, DATE_FORMAT)
+                    .map_err(|e| DatabaseError::Corrupt(e.to_string()))?,
+            )),
+            _ => Err(DatabaseError::Corrupt(format!(
+                "Date is not a string: {}",
+                scalar
+            ))),
+        },
+        Value::Object(_) => Err(DatabaseError::Corrupt("Date is an object".to_string())),
+    }
+}
+
+/// The ways in which reading from or writing to the database can fail.
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// A task, project, checklist item or field which doesn't exist.
+    NotFound(String),
+    /// The document doesn't have the layout we expect.
+    Corrupt(String),
+    Io(io::Error),
+    /// The document was written by a newer version of tarsk.
+    SchemaMismatch {
+        version: u64,
+    },
+    /// An operation which doesn't make sense, e.g. setting a field to a value of the wrong type.
+    Invalid(String),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use DatabaseError::*;
+        match self {
+            NotFound(message) | Invalid(message) => f.write_str(message),
+            Corrupt(message) => write!(f, "Corrupt database: {}", message),
+            Io(e) => write!(f, "I/O error: {}", e),
+            SchemaMismatch { version } => write!(
+                f,
+                "The database uses schema version {}, but this version of tarsk only supports up to {}",
+                version, SCHEMA_VERSION
+            ),
+        }
+    }
+}
+
+impl Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DatabaseError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DatabaseError {
+    fn from(e: io::Error) -> Self {
+        DatabaseError::Io(e)
+    }
+}
+
+impl From<AutomergeError> for DatabaseError {
+    fn from(e: AutomergeError) -> Self {
+        match e {
+            AutomergeError::MissingHash(_) => DatabaseError::NotFound(e.to_string()),
+            AutomergeError::InvalidIndex(_) | AutomergeError::EmptyStringKey => {
+                DatabaseError::Invalid(e.to_string())
+            }
+            _ => DatabaseError::Corrupt(e.to_string()),
+        }
+    }
+}
+
+/// Parses a value read from the document, which is corrupt if it doesn't parse.
+fn parse_stored<T: FromStr<Err = DatabaseError>>(s: &str) -> Result<T> {
+    s.parse()
+        .map_err(|e: DatabaseError| DatabaseError::Corrupt(e.to_string()))
+}
+
+/// What `Database::recover` did with an unreadable database.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Recovery {
+    /// Where the unreadable database was moved to.
+    pub quarantined: PathBuf,
+    /// How many changes could be read back.
+    pub changes: usize,
+}
+
+/// A problem with a task in the database, see `Database::diagnose`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    /// Where the task is in the underlying task list.
+    pub index: usize,
+    pub id: Option<Uuid>,
+    pub problem: String,
+    /// Whether `Database::repair` can fix the problem.
+    pub repairable: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Status {
+    #[default]
+    Todo,
+    InProgress,
+    Done,
+    Cancelled,
+}
+
+impl Status {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Status::Done | Status::Cancelled)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Status::*;
+        f.write_str(match self {
+            Todo => "todo",
+            InProgress => "in-progress",
+            Done => "done",
+            Cancelled => "cancelled",
+        })
+    }
+}
+
+impl FromStr for Status {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use Status::*;
+        match s {
+            "todo" => Ok(Todo),
+            "in-progress" => Ok(InProgress),
+            "done" => Ok(Done),
+            "cancelled" => Ok(Cancelled),
+            _ => Err(DatabaseError::Invalid(format!("Unknown status `{}`", s))),
+        }
+    }
+}
+
+/// How a task repeats once it has been completed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Recurrence {
+    Daily,
+    /// On each of the given days of the week.
+    Weekly(Vec<Weekday>),
+    /// On the given day of the month, or the last day of months which are too short.
+    /// `None` repeats on the day of the scheduled date.
+    Monthly(Option<u32>),
+    /// The given number of days after the task was last completed.
+    AfterCompletion(u32),
+}
+
+impl Recurrence {
+    /// Returns the date of the occurrence after the one scheduled on `scheduled`.
+    pub fn next(&self, scheduled: NaiveDate, completed_on: NaiveDate) -> NaiveDate {
+        match self {
+            Recurrence::Daily => scheduled + Duration::days(1),
+            // An empty set of weekdays repeats on the same day every week.
+            Recurrence::Weekly(weekdays) if weekdays.is_empty() => scheduled + Duration::weeks(1),
+            Recurrence::Weekly(weekdays) => {
+                let mut next = scheduled + Duration::days(1);
+                while !weekdays.contains(&next.weekday()) {
+                    next += Duration::days(1);
+                }
+                next
+            }
+            Recurrence::Monthly(day) => {
+                let (year, month) = if scheduled.month() == 12 {
+                    (scheduled.year() + 1, 1)
+                } else {
+                    (scheduled.year(), scheduled.month() + 1)
+                };
+                (1..=day.unwrap_or_else(|| scheduled.day()))
+                    .rev()
+                    .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+                    .unwrap()
+            }
+            Recurrence::AfterCompletion(days) => completed_on + Duration::days(*days as i64),
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Recurrence::Daily => write!(f, "daily"),
+            Recurrence::Weekly(weekdays) => {
+                let weekdays: Vec<&str> = weekdays.iter().map(weekday_name).collect();
+                write!(f, "weekly:{}", weekdays.join(","))
+            }
+            Recurrence::Monthly(None) => write!(f, "monthly"),
+            Recurrence::Monthly(Some(day)) => write!(f, "monthly:{}", day),
+            Recurrence::AfterCompletion(days) => write!(f, "after:{}", days),
+        }
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, argument) = match s.split_once(':') {
+            None => (s, None),
+            Some((kind, argument)) => (kind, Some(argument)),
+        };
+
+        match (kind, argument) {
+            ("daily", None) => Ok(Recurrence::Daily),
+            ("monthly", None) => Ok(Recurrence::Monthly(None)),
+            ("monthly", Some(day)) => match day.parse() {
+                Ok(day @ 1..=31) => Ok(Recurrence::Monthly(Some(day))),
+                _ => Err(DatabaseError::Invalid(format!(
+                    "Invalid day of the month `{}`",
+                    day
+                ))),
+            },
+            ("weekly", Some(weekdays)) => {
+                let mut parsed = vec![];
+                for weekday in weekdays.split(',').filter(|weekday| !weekday.is_empty()) {
+                    let weekday = WEEKDAYS
+                        .iter()
+                        .copied()
+                        .find(|candidate| weekday_name(candidate) == weekday)
+                        .ok_or_else(|| {
+                            DatabaseError::Invalid(format!("Unknown weekday `{}`", weekday))
+                        })?;
+                    parsed.push(weekday);
+                }
+                Ok(Recurrence::Weekly(parsed))
+            }
+            ("after", Some(days)) => {
+                Ok(Recurrence::AfterCompletion(days.parse().map_err(|_| {
+                    DatabaseError::Invalid(format!("Invalid number of days `{}`", days))
+                })?))
+            }
+            _ => Err(DatabaseError::Invalid(format!(
+                "Unknown recurrence `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+fn weekday_name(weekday: &Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "mon",
+        Weekday::Tue => "tue",
+        Weekday::Wed => "wed",
+        Weekday::Thu => "thu",
+        Weekday::Fri => "fri",
+        Weekday::Sat => "sat",
+        Weekday::Sun => "sun",
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProjectStatus {
+    Active,
+    OnHold,
+    Completed,
+}
+
+impl ProjectStatus {
+    pub fn next(&self) -> ProjectStatus {
+        use ProjectStatus::*;
+        match self {
+            Active => OnHold,
+            OnHold => Completed,
+            Completed => Active,
+        }
+    }
+}
+
+impl fmt::Display for ProjectStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ProjectStatus::*;
+        f.write_str(match self {
+            Active => "active",
+            OnHold => "on-hold",
+            Completed => "completed",
+        })
+    }
+}
+
+impl FromStr for ProjectStatus {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use ProjectStatus::*;
+        match s {
+            "active" => Ok(Active),
+            "on-hold" => Ok(OnHold),
+            "completed" => Ok(Completed),
+            _ => Err(DatabaseError::Invalid(format!(
+                "Unknown project status `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+/// Follows org-mode's `[#A]` through `[#C]`, where `A` is the most important.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Priority {
+    A,
+    B,
+    C,
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Priority::*;
+        f.write_str(match self {
+            A => "A",
+            B => "B",
+            C => "C",
+        })
+    }
+}
+
+impl FromStr for Priority {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use Priority::*;
+        match s {
+            "A" => Ok(A),
+            "B" => Ok(B),
+            "C" => Ok(C),
+            _ => Err(DatabaseError::Invalid(format!("Unknown priority `{}`", s))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SortOrder {
+    /// The order tasks are stored in, which is newest first.
+    Manual,
+    /// Most important first, with unprioritized tasks last.
+    Priority,
+    /// Earliest first, with unscheduled tasks last.
+    Scheduled,
+    /// Oldest first.
+    Created,
+    /// Alphabetical, ignoring case.
+    Title,
+}
+
+impl SortOrder {
+    pub fn next(&self) -> SortOrder {
+        use SortOrder::*;
+        match self {
+            Manual => Priority,
+            Priority => Scheduled,
+            Scheduled => Created,
+            Created => Title,
+            Title => Manual,
+        }
+    }
+
+    /// Sorts the tasks, keeping tasks which compare equal in their original order.
+    pub fn sort<'a>(&self, tasks: Vec<Task<'a>>) -> Result<Vec<Task<'a>>> {
+        // Pair values with whether they're missing to sort missing ones last.
+        let mut keyed = vec![];
+        for task in tasks {
+            let key = match self {
+                SortOrder::Manual => SortKey::None,
+                SortOrder::Priority => {
+                    let priority = task.priority()?;
+                    SortKey::Priority(priority.is_none(), priority)
+                }
+                SortOrder::Scheduled => {
+                    let scheduled = task.scheduled()?;
+                    SortKey::Scheduled(scheduled.is_none(), scheduled)
+                }
+                SortOrder::Created => {
+                    let created_at = task.created_at()?;
+                    SortKey::Created(created_at.is_none(), created_at)
+                }
+                SortOrder::Title => SortKey::Title(task.title()?.to_lowercase()),
+            };
+            keyed.push((key, task));
+        }
+        keyed.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(keyed.into_iter().map(|(_, task)| task).collect())
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SortOrder::*;
+        f.write_str(match self {
+            Manual => "manual",
+            Priority => "priority",
+            Scheduled => "scheduled",
+            Created => "created",
+            Title => "title",
+        })
+    }
+}
+
+#[derive(Eq, Ord, PartialEq, PartialOrd)]
+enum SortKey {
+    None,
+    Priority(bool, Option<Priority>),
+    Scheduled(bool, Option<NaiveDate>),
+    Created(bool, Option<DateTime<Utc>>),
+    Title(String),
+}
+
+/// The type of a custom field, as declared in the schema at the root of the document.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FieldType {
+    String,
+    Number,
+    Date,
+    /// A choice between a fixed set of options.
+    Enum(Vec<String>),
+    Boolean,
+}
+
+impl FieldType {
+    pub fn validate(&self, value: &FieldValue) -> Result<()> {
+        match (self, value) {
+            (FieldType::String, FieldValue::String(_))
+            | (FieldType::Number, FieldValue::Number(_))
+            | (FieldType::Date, FieldValue::Date(_))
+            | (FieldType::Boolean, FieldValue::Boolean(_)) => Ok(()),
+            (FieldType::Enum(options), FieldValue::Enum(option)) => {
+                if options.contains(option) {
+                    Ok(())
+                } else {
+                    Err(DatabaseError::Invalid(format!(
+                        "`{}` is not one of {}",
+                        option,
+                        options.join(", ")
+                    )))
+                }
+            }
+            _ => Err(self.mismatch(value)),
+        }
+    }
+
+    fn mismatch<V: fmt::Display>(&self, value: V) -> DatabaseError {
+        DatabaseError::Invalid(format!("Expected a {} but got `{}`", self, value))
+    }
+
+    /// Parses a value of this type as typed in by the user.
+    pub fn parse_value(&self, s: &str) -> Result<FieldValue> {
+        let value = match self {
+            FieldType::String => FieldValue::String(s.to_string()),
+            FieldType::Number => FieldValue::Number(s.parse().map_err(|_| self.mismatch(s))?),
+            FieldType::Date => FieldValue::Date(
+                NaiveDate::parse_from_str(s, DATE_FORMAT).map_err(|_| self.mismatch(s))?,
+            ),
+            FieldType::Enum(_) => FieldValue::Enum(s.to_string()),
+            FieldType::Boolean => FieldValue::Boolean(s.parse().map_err(|_| self.mismatch(s))?),
+        };
+        self.validate(&value)?;
+        Ok(value)
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldType::String => write!(f, "string"),
+            FieldType::Number => write!(f, "number"),
+            FieldType::Date => write!(f, "date"),
+            FieldType::Enum(options) => write!(f, "enum:{}", options.join(",")),
+            FieldType::Boolean => write!(f, "boolean"),
+        }
+    }
+}
+
+impl FromStr for FieldType {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s == "string" => Ok(FieldType::String),
+            None if s == "number" => Ok(FieldType::Number),
+            None if s == "date" => Ok(FieldType::Date),
+            None if s == "boolean" => Ok(FieldType::Boolean),
+            Some(("enum", options)) => Ok(FieldType::Enum(
+                options
+                    .split(',')
+                    .filter(|option| !option.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            )),
+            _ => Err(DatabaseError::Invalid(format!(
+                "Unknown field type `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    String(String),
+    Number(f64),
+    Date(NaiveDate),
+    Enum(String),
+    Boolean(bool),
+}
+
+impl FieldValue {
+    fn to_scalar(&self) -> ScalarValue {
+        match self {
+            FieldValue::String(s) | FieldValue::Enum(s) => ScalarValue::Str(s.as_str().into()),
+            FieldValue::Number(n) => ScalarValue::F64(*n),
+            FieldValue::Date(date) => ScalarValue::Str(date.format(DATE_FORMAT).to_string().into()),
+            FieldValue::Boolean(b) => ScalarValue::Boolean(*b),
+        }
+    }
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldValue::String(s) | FieldValue::Enum(s) => write!(f, "{}", s),
+            FieldValue::Number(n) => write!(f, "{}", n),
+            FieldValue::Date(date) => write!(f, "{}", date),
+            FieldValue::Boolean(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChecklistItem {
+    pub text: String,
+    pub checked: bool,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Comment {
+    /// The id of the device which wrote the comment, see `Database::device_id`.
+    pub author: String,
+    pub created_at: DateTime<Utc>,
+    pub text: String,
+}
+
+/// How tasks are grouped in an `EstimateReport`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GroupBy {
+    Tag,
+    Project,
+}
+
+impl fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use GroupBy::*;
+        f.write_str(match self {
+            Tag => "tag",
+            Project => "project",
+        })
+    }
+}
+
+impl FromStr for GroupBy {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use GroupBy::*;
+        match s {
+            "tag" => Ok(Tag),
+            "project" => Ok(Project),
+            _ => Err(DatabaseError::Invalid(format!("Unknown grouping `{}`", s))),
+        }
+    }
+}
+
+/// The estimated and actual time of a group of tasks.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EstimateReport {
+    /// The tag or project name, or `None` for tasks without one.
+    pub group: Option<String>,
+    pub tasks: usize,
+    pub estimated: Duration,
+    pub actual: Duration,
+}
+
+/// An interval of time spent working on a task.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClockEntry {
+    /// The id of the device which clocked in, see `Database::device_id`.
+    pub actor: String,
+    pub start: DateTime<Utc>,
+    /// When the clock was stopped, or `None` while it's still running.
+    pub end: Option<DateTime<Utc>>,
+}
+
+impl ClockEntry {
+    pub fn duration(&self, now: DateTime<Utc>) -> Duration {
+        self.end.unwrap_or(now) - self.start
+    }
+
+    /// Returns how much of the entry falls between `from` and `to`.
+    pub fn overlap(&self, from: DateTime<Utc>, to: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+        let start = self.start.max(from);
+        let end = self.end.unwrap_or(now).min(to);
+        (end - start).max(Duration::zero())
+    }
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct TaskImage {
+    pub title: String,
+    pub scheduled: Option<NaiveDate>,
+    pub deadline: Option<NaiveDate>,
+    pub status: Status,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub priority: Option<Priority>,
+    pub recurrence: Option<Recurrence>,
+    pub tags: Vec<String>,
+    pub checklist: Vec<ChecklistItem>,
+    pub body: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_task() {
+        let database = Database::new().unwrap();
+
+        let task = database.add_task().unwrap();
+        let task_image = task.image().unwrap();
+        assert_eq!(
+            task_image,
+            TaskImage {
+                title: "".to_string(),
+                scheduled: None,
+                deadline: None,
+                status: Status::Todo,
+                completed_at: None,
+                priority: None,
+                recurrence: None,
+                tags: vec![],
+                checklist: vec![],
+                body: "".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_list_tasks() {
+        let database = Database::new().unwrap();
+        let task = database.add_task().unwrap();
+        task.splice_title(0, 0, "some text").unwrap();
+
+        let tasks = database.list_tasks().unwrap();
+        assert_eq!(tasks.len(), 1);
+        let task = &tasks[0];
+        assert_eq!(task.title().unwrap(), "some text");
+    }
+
+    #[test]
+    fn test_splice_title() {
+        let database = Database::new().unwrap();
+
+        let task = database.add_task().unwrap();
+        task.splice_title(0, 0, "hello world!").unwrap();
+        assert_eq!(task.title().unwrap(), "hello world!".to_string());
+    }
+
+    #[test]
+    fn test_splice_body() {
+        let database = Database::new().unwrap();
+
+        let task = database.add_task().unwrap();
+        task.splice_body(0, 0, "hello world!").unwrap();
+        assert_eq!(task.body().unwrap(), "hello world!".to_string());
+    }
+
+    #[test]
+    fn test_scheduled() {
+        let database = Database::new().unwrap();
+
+        let task = database.add_task().unwrap();
+        assert_eq!(task.scheduled().unwrap(), None);
+
+        let date = NaiveDate::from_ymd(2022, 11, 5);
+        task.set_scheduled(date).unwrap();
+        assert_eq!(task.scheduled().unwrap(), Some(date));
+        assert_eq!(task.image().unwrap().scheduled, Some(date));
+
+        task.clear_scheduled().unwrap();
+        assert_eq!(task.scheduled().unwrap(), None);
+    }
+
+    #[test]
+    fn test_scheduled_concurrent_edits() {
+        let database_a = Database::new().unwrap();
+        database_a.add_task().unwrap();
+        let database_b = Database::from_bytes(&database_a.to_bytes()).unwrap();
+
+        let date_a = NaiveDate::from_ymd(2022, 11, 5);
+        let date_b = NaiveDate::from_ymd(2022, 11, 6);
+        database_a.list_tasks().unwrap()[0]
+            .set_scheduled(date_a)
+            .unwrap();
+        database_b.list_tasks().unwrap()[0]
+            .set_scheduled(date_b)
+            .unwrap();
+
+        database_a
+            .apply_changes(database_b.get_changes(&[]).unwrap())
+            .unwrap();
+        database_b
+            .apply_changes(database_a.get_changes(&[]).unwrap())
+            .unwrap();
+
+        let task_a = &database_a.list_tasks().unwrap()[0];
+        let task_b = &database_b.list_tasks().unwrap()[0];
+        let winner = task_a.scheduled().unwrap();
+        assert!(winner == Some(date_a) || winner == Some(date_b));
+        assert_eq!(task_b.scheduled().unwrap(), winner);
+
+        let mut conflicts = task_a.scheduled_conflicts().unwrap();
+        conflicts.sort();
+        assert_eq!(conflicts, vec![Some(date_a), Some(date_b)]);
+
+        task_a.set_scheduled(date_a).unwrap();
+        assert_eq!(task_a.scheduled_conflicts().unwrap(), vec![Some(date_a)]);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let database = Database::new().unwrap();
+
+        let task = database.add_task().unwrap();
+        assert_eq!(task.deadline().unwrap(), None);
+
+        let date = NaiveDate::from_ymd(2022, 11, 5);
+        task.set_deadline(date).unwrap();
+        assert_eq!(task.deadline().unwrap(), Some(date));
+        assert_eq!(task.image().unwrap().deadline, Some(date));
+        assert_eq!(task.scheduled().unwrap(), None);
+
+        task.clear_deadline().unwrap();
+        assert_eq!(task.deadline().unwrap(), None);
+    }
+
+    #[test]
+    fn test_list_due_tasks() {
+        let database = Database::new().unwrap();
+        let today = NaiveDate::from_ymd(2022, 11, 5);
+        for (title, day) in [
+            ("overdue", 1),
+            ("today", 5),
+            ("soon", 7),
+            ("later", 20),
+            ("finished", 1),
+        ] {
+            let task = database.add_task().unwrap();
+            task.splice_title(0, 0, title).unwrap();
+            task.set_deadline(NaiveDate::from_ymd(2022, 11, day))
+                .unwrap();
+            if title == "finished" {
+                task.set_status(Status::Done).unwrap();
+            }
+        }
+        database.add_task().unwrap();
+
+        let titles = |tasks: Vec<Task>| -> Vec<String> {
+            let mut titles: Vec<String> = tasks.iter().map(|task| task.title().unwrap()).collect();
+            titles.sort();
+            titles
+        };
+        assert_eq!(
+            titles(database.list_overdue_tasks(today).unwrap()),
+            vec!["overdue"]
+        );
+        assert_eq!(
+            titles(database.list_tasks_due_within(today, 3).unwrap()),
+            vec!["soon", "today"]
+        );
+    }
+
+    #[test]
+    fn test_set_status() {
+        let database = Database::new().unwrap();
+
+        let task = database.add_task().unwrap();
+        assert_eq!(task.status().unwrap(), Status::Todo);
+
+        task.set_status(Status::InProgress).unwrap();
+        assert_eq!(task.status().unwrap(), Status::InProgress);
+        assert_eq!(task.image().unwrap().completed_at, None);
+
+        task.set_status(Status::Done).unwrap();
+        let task_image = task.image().unwrap();
+        assert_eq!(task_image.status, Status::Done);
+        assert!(task_image.completed_at.is_some());
+
+        // Moving between finished states keeps the original completion time.
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        task.set_status(Status::Cancelled).unwrap();
+        assert_eq!(task.image().unwrap().completed_at, task_image.completed_at);
+
+        task.set_status(Status::Todo).unwrap();
+        assert_eq!(task.image().unwrap().completed_at, None);
+    }
+
+    #[test]
+    fn test_status_string_roundtrip() {
+        for status in [
+            Status::Todo,
+            Status::InProgress,
+            Status::Done,
+            Status::Cancelled,
+        ] {
+            assert_eq!(status.to_string().parse::<Status>().unwrap(), status);
+        }
+    }
+
+    #[test]
+    fn test_get_task() {
+        let database = Database::new().unwrap();
+        let task = database.add_task().unwrap();
+        task.splice_title(0, 0, "first").unwrap();
+        let id = task.id().unwrap();
+
+        database.add_task().unwrap();
+        task.archive().unwrap();
+
+        let task = database.get_task(id).unwrap().unwrap();
+        assert_eq!(task.title().unwrap(), "first");
+        assert!(database.get_task(Uuid::new_v4()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_subtasks() {
+        let database = Database::new().unwrap();
+        let parent = database.add_task().unwrap();
+        parent.splice_title(0, 0, "parent").unwrap();
+        let child = parent.add_subtask().unwrap();
+        child.splice_title(0, 0, "child").unwrap();
+        child
+            .add_subtask()
+            .unwrap()
+            .splice_title(0, 0, "grandchild")
+            .unwrap();
+
+        let subtasks = parent.subtasks().unwrap();
+        assert_eq!(subtasks.len(), 1);
+        assert_eq!(subtasks[0].title().unwrap(), "child");
+        assert_eq!(
+            child.parent_task().unwrap().unwrap().title().unwrap(),
+            "parent"
+        );
+        assert!(parent.parent_task().unwrap().is_none());
+
+        let tree: Vec<(usize, String)> = database
+            .list_task_tree()
+            .unwrap()
+            .into_iter()
+            .map(|(depth, task)| (depth, task.title().unwrap()))
+            .collect();
+        assert_eq!(
+            tree,
+            vec![
+                (0, "parent".to_string()),
+                (1, "child".to_string()),
+                (2, "grandchild".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_move_to() {
+        let database = Database::new().unwrap();
+        let parent = database.add_task().unwrap();
+        let child = parent.add_subtask().unwrap();
+
+        assert!(parent.move_to(Some(&child)).is_err());
+        assert!(parent.move_to(Some(&parent)).is_err());
+
+        child.move_to(None).unwrap();
+        assert!(child.parent_task().unwrap().is_none());
+        parent.move_to(Some(&child)).unwrap();
+        assert_eq!(
+            parent.parent_task().unwrap().unwrap().id().unwrap(),
+            child.id().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_move_to_concurrent_cycle() {
+        let database_a = Database::new().unwrap();
+        let id_x = database_a.add_task().unwrap().id().unwrap();
+        let id_y = database_a.add_task().unwrap().id().unwrap();
+        let database_b = Database::from_bytes(&database_a.to_bytes()).unwrap();
+
+        let x = database_a.get_task(id_x).unwrap().unwrap();
+        let y = database_a.get_task(id_y).unwrap().unwrap();
+        x.move_to(Some(&y)).unwrap();
+
+        let x = database_b.get_task(id_x).unwrap().unwrap();
+        let y = database_b.get_task(id_y).unwrap().unwrap();
+        y.move_to(Some(&x)).unwrap();
+
+        database_a
+            .apply_changes(database_b.get_changes(&[]).unwrap())
+            .unwrap();
+        database_b
+            .apply_changes(database_a.get_changes(&[]).unwrap())
+            .unwrap();
+
+        let promoted = id_x.min(id_y);
+        for database in [&database_a, &database_b] {
+            let tree: Vec<(usize, Uuid)> = database
+                .list_task_tree()
+                .unwrap()
+                .into_iter()
+                .map(|(depth, task)| (depth, task.id().unwrap()))
+                .collect();
+            assert_eq!(tree, vec![(0, promoted), (1, id_x.max(id_y))]);
+        }
+    }
+
+    #[test]
+    fn test_tags() {
+        let database = Database::new().unwrap();
+        let first = database.add_task().unwrap();
+        first.add_tag("work").unwrap();
+        first.add_tag("errand").unwrap();
+        first.add_tag("work").unwrap();
+        let second = database.add_task().unwrap();
+        second.add_tag("home").unwrap();
+        second.add_tag("errand").unwrap();
+
+        assert_eq!(first.tags().unwrap(), vec!["errand", "work"]);
+        assert_eq!(
+            database.list_tags().unwrap(),
+            vec!["errand", "home", "work"]
+        );
+        assert_eq!(database.list_tasks_with_tag("errand").unwrap().len(), 2);
+        assert_eq!(database.list_tasks_with_tag("missing").unwrap().len(), 0);
+
+        first.remove_tag("work").unwrap();
+        first.remove_tag("missing").unwrap();
+        assert_eq!(first.tags().unwrap(), vec!["errand"]);
+        assert_eq!(database.list_tasks_with_tag("work").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_tags_concurrent_edits() {
+        let database_a = Database::new().unwrap();
+        database_a.add_task().unwrap().add_tag("shared").unwrap();
+        let database_b = Database::from_bytes(&database_a.to_bytes()).unwrap();
+
+        let task_a = &database_a.list_tasks().unwrap()[0];
+        task_a.add_tag("work").unwrap();
+        task_a.add_tag("urgent").unwrap();
+        task_a.remove_tag("shared").unwrap();
+
+        let task_b = &database_b.list_tasks().unwrap()[0];
+        task_b.add_tag("work").unwrap();
+        task_b.add_tag("home").unwrap();
+        task_b.remove_tag("shared").unwrap();
+        task_b.add_tag("shared").unwrap();
+
+        database_a
+            .apply_changes(database_b.get_changes(&[]).unwrap())
+            .unwrap();
+        database_b
+            .apply_changes(database_a.get_changes(&[]).unwrap())
+            .unwrap();
+
+        let expected = vec!["home", "shared", "urgent", "work"];
+        assert_eq!(
+            database_a.list_tasks().unwrap()[0].tags().unwrap(),
+            expected
+        );
+        assert_eq!(
+            database_b.list_tasks().unwrap()[0].tags().unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_recurrence_next() {
+        let completed_on = NaiveDate::from_ymd(2022, 11, 20);
+        let next = |recurrence: Recurrence, scheduled| recurrence.next(scheduled, completed_on);
+
+        // 2022-11-04 is a Friday.
+        let friday = NaiveDate::from_ymd(2022, 11, 4);
+        assert_eq!(
+            next(Recurrence::Daily, friday),
+            NaiveDate::from_ymd(2022, 11, 5)
+        );
+        assert_eq!(
+            next(Recurrence::Weekly(vec![Weekday::Mon, Weekday::Fri]), friday),
+            NaiveDate::from_ymd(2022, 11, 7)
+        );
+        assert_eq!(
+            next(Recurrence::Weekly(vec![]), friday),
+            NaiveDate::from_ymd(2022, 11, 11)
+        );
+        assert_eq!(
+            next(Recurrence::Monthly(None), NaiveDate::from_ymd(2022, 1, 31)),
+            NaiveDate::from_ymd(2022, 2, 28)
+        );
+        assert_eq!(
+            next(Recurrence::Monthly(None), NaiveDate::from_ymd(2022, 12, 15)),
+            NaiveDate::from_ymd(2023, 1, 15)
+        );
+
+        // The anchor day survives months which are too short for it.
+        let mut scheduled = NaiveDate::from_ymd(2024, 1, 31);
+        let mut dates = vec![];
+        for _ in 0..4 {
+            scheduled = next(Recurrence::Monthly(Some(31)), scheduled);
+            dates.push(scheduled);
+        }
+        assert_eq!(
+            dates,
+            vec![
+                NaiveDate::from_ymd(2024, 2, 29),
+                NaiveDate::from_ymd(2024, 3, 31),
+                NaiveDate::from_ymd(2024, 4, 30),
+                NaiveDate::from_ymd(2024, 5, 31),
+            ]
+        );
+        assert_eq!(
+            next(Recurrence::AfterCompletion(3), friday),
+            NaiveDate::from_ymd(2022, 11, 23)
+        );
+    }
+
+    #[test]
+    fn test_recurrence_string_roundtrip() {
+        for recurrence in [
+            Recurrence::Daily,
+            Recurrence::Weekly(vec![Weekday::Tue, Weekday::Sun]),
+            Recurrence::Weekly(vec![]),
+            Recurrence::Monthly(None),
+            Recurrence::Monthly(Some(31)),
+            Recurrence::AfterCompletion(10),
+        ] {
+            assert_eq!(
+                recurrence.to_string().parse::<Recurrence>().unwrap(),
+                recurrence
+            );
+        }
+        assert!("weekly:someday".parse::<Recurrence>().is_err());
+        assert!("monthly:32".parse::<Recurrence>().is_err());
+    }
+
+    #[test]
+    fn test_complete_occurrence() {
+        let database = Database::new().unwrap();
+        let task = database.add_task().unwrap();
+        task.set_scheduled(NaiveDate::from_ymd(2022, 11, 4))
+            .unwrap();
+        task.set_deadline(NaiveDate::from_ymd(2022, 11, 6)).unwrap();
+        task.set_recurrence(Some(Recurrence::Daily)).unwrap();
+
+        task.complete_occurrence(NaiveDate::from_ymd(2022, 11, 4))
+            .unwrap();
+        let task_image = task.image().unwrap();
+        assert_eq!(task_image.status, Status::Todo);
+        assert_eq!(task_image.scheduled, Some(NaiveDate::from_ymd(2022, 11, 5)));
+        assert_eq!(task_image.deadline, Some(NaiveDate::from_ymd(2022, 11, 7)));
+
+        task.set_status(Status::Done).unwrap();
+        assert_eq!(task.status().unwrap(), Status::Todo);
+        assert_eq!(
+            task.scheduled().unwrap(),
+            Some(NaiveDate::from_ymd(2022, 11, 6))
+        );
+
+        task.set_recurrence(None).unwrap();
+        assert!(task
+            .complete_occurrence(NaiveDate::from_ymd(2022, 11, 4))
+            .is_err());
+    }
+
+    #[test]
+    fn test_complete_occurrence_monthly() {
+        let database = Database::new().unwrap();
+        let task = database.add_task().unwrap();
+        task.set_scheduled(NaiveDate::from_ymd(2023, 1, 31))
+            .unwrap();
+        // Written before the day was stored in the rule.
+        task.set_recurrence(Some(Recurrence::Monthly(None)))
+            .unwrap();
+
+        let mut dates = vec![];
+        for _ in 0..3 {
+            task.complete_occurrence(NaiveDate::from_ymd(2023, 1, 1))
+                .unwrap();
+            dates.push(task.scheduled().unwrap().unwrap());
+        }
+        assert_eq!(
+            dates,
+            vec![
+                NaiveDate::from_ymd(2023, 2, 28),
+                NaiveDate::from_ymd(2023, 3, 31),
+                NaiveDate::from_ymd(2023, 4, 30),
+            ]
+        );
+        assert_eq!(
+            task.recurrence().unwrap(),
+            Some(Recurrence::Monthly(Some(31)))
+        );
+    }
+
+    #[test]
+    fn test_complete_occurrence_concurrent() {
+        let database_a = Database::new().unwrap();
+        let task = database_a.add_task().unwrap();
+        task.set_scheduled(NaiveDate::from_ymd(2022, 11, 4))
+            .unwrap();
+        task.set_recurrence(Some(Recurrence::Weekly(vec![Weekday::Fri])))
+            .unwrap();
+        let database_b = Database::from_bytes(&database_a.to_bytes()).unwrap();
+
+        database_a.list_tasks().unwrap()[0]
+            .complete_occurrence(NaiveDate::from_ymd(2022, 11, 4))
+            .unwrap();
+        database_b.list_tasks().unwrap()[0]
+            .complete_occurrence(NaiveDate::from_ymd(2022, 11, 5))
+            .unwrap();
+
+        database_a
+            .apply_changes(database_b.get_changes(&[]).unwrap())
+            .unwrap();
+        database_b
+            .apply_changes(database_a.get_changes(&[]).unwrap())
+            .unwrap();
+
+        for database in [&database_a, &database_b] {
+            let tasks = database.list_tasks().unwrap();
+            assert_eq!(tasks.len(), 1);
+            assert_eq!(
+                tasks[0].scheduled().unwrap(),
+                Some(NaiveDate::from_ymd(2022, 11, 11))
+            );
+            assert_eq!(tasks[0].scheduled_conflicts().unwrap().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_set_priority() {
+        let database = Database::new().unwrap();
+
+        let task = database.add_task().unwrap();
+        assert_eq!(task.priority().unwrap(), None);
+        task.set_priority(Some(Priority::B)).unwrap();
+        assert_eq!(task.priority().unwrap(), Some(Priority::B));
+        assert_eq!(task.image().unwrap().priority, Some(Priority::B));
+        task.set_priority(None).unwrap();
+        assert_eq!(task.priority().unwrap(), None);
+    }
+
+    #[test]
+    fn test_list_tasks_sorted() {
+        let database = Database::new().unwrap();
+        for (title, priority, day, created_at) in [
+            ("banana", Some(Priority::C), Some(3), 2000),
+            ("Apple", None, Some(1), 4000),
+            ("cherry", Some(Priority::A), None, 1000),
+            ("date", Some(Priority::B), Some(2), 3000),
+        ] {
+            let task = database.add_task().unwrap();
+            task.splice_title(0, 0, title).unwrap();
+            task.set_priority(priority).unwrap();
+            if let Some(day) = day {
+                task.set_scheduled(NaiveDate::from_ymd(2022, 11, day))
+                    .unwrap();
+            }
+            // Tasks created in the same millisecond would tie.
+            database
+                .doc
+                .lock()
+                .unwrap()
+                .put(
+                    &task.task_obj_id,
+                    "created_at",
+                    ScalarValue::Timestamp(created_at),
+                )
+                .unwrap();
+        }
+
+        let titles = |order| -> Vec<String> {
+            database
+                .list_tasks_sorted(order)
+                .unwrap()
+                .iter()
+                .map(|task| task.title().unwrap())
+                .collect()
+        };
+        assert_eq!(
+            titles(SortOrder::Manual),
+            vec!["date", "cherry", "Apple", "banana"]
+        );
+        assert_eq!(
+            titles(SortOrder::Priority),
+            vec!["cherry", "date", "banana", "Apple"]
+        );
+        assert_eq!(
+            titles(SortOrder::Scheduled),
+            vec!["Apple", "date", "banana", "cherry"]
+        );
+        assert_eq!(
+            titles(SortOrder::Title),
+            vec!["Apple", "banana", "cherry", "date"]
+        );
+        assert_eq!(
+            titles(SortOrder::Created),
+            vec!["cherry", "banana", "date", "Apple"]
+        );
+    }
+
+    #[test]
+    fn test_move_task() {
+        let database = Database::new().unwrap();
+        for title in ["c", "b", "a"] {
+            database
+                .add_task()
+                .unwrap()
+                .splice_title(0, 0, title)
+                .unwrap();
+        }
+        let titles = || -> Vec<String> {
+            database
+                .list_tasks()
+                .unwrap()
+                .iter()
+                .map(|task| task.title().unwrap())
+                .collect()
+        };
+        let id = |index: usize| database.list_tasks().unwrap()[index].id().unwrap();
+        assert_eq!(titles(), vec!["a", "b", "c"]);
+
+        database.move_task(id(0), 2).unwrap();
+        assert_eq!(titles(), vec!["b", "c", "a"]);
+
+        database.move_task(id(2), 1).unwrap();
+        assert_eq!(titles(), vec!["b", "a", "c"]);
+
+        database.move_task(id(1), 0).unwrap();
+        assert_eq!(titles(), vec!["a", "b", "c"]);
+
+        // Bisecting the same gap over and over eventually respaces the tasks.
+        for _ in 0..100 {
+            let moved = id(2);
+            database.move_task(moved, 1).unwrap();
+            assert_eq!(id(1), moved);
+        }
+        assert_eq!(titles(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_move_task_concurrent() {
+        let database_a = Database::new().unwrap();
+        for title in ["c", "b", "a"] {
+            database_a
+                .add_task()
+                .unwrap()
+                .splice_title(0, 0, title)
+                .unwrap();
+        }
+        let database_b = Database::from_bytes(&database_a.to_bytes()).unwrap();
+
+        let id_a = database_a.list_tasks().unwrap()[0].id().unwrap();
+        let id_c = database_a.list_tasks().unwrap()[2].id().unwrap();
+        database_a.move_task(id_a, 2).unwrap();
+        database_b.move_task(id_a, 1).unwrap();
+        database_b.move_task(id_c, 0).unwrap();
+
+        database_a
+            .apply_changes(database_b.get_changes(&[]).unwrap())
+            .unwrap();
+        database_b
+            .apply_changes(database_a.get_changes(&[]).unwrap())
+            .unwrap();
+
+        let titles = |database: &Database| -> Vec<String> {
+            database
+                .list_tasks()
+                .unwrap()
+                .iter()
+                .map(|task| task.title().unwrap())
+                .collect()
+        };
+        let mut sorted_titles = titles(&database_a);
+        assert_eq!(sorted_titles, titles(&database_b));
+        sorted_titles.sort();
+        assert_eq!(sorted_titles, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_checklist() {
+        let database = Database::new().unwrap();
+        let task = database.add_task().unwrap();
+        task.add_checklist_item("eggs").unwrap();
+        task.add_checklist_item("milk").unwrap();
+        task.add_checklist_item("bread").unwrap();
+
+        task.toggle_checklist_item(1).unwrap();
+        task.splice_checklist_item(0, 4, 0, " (dozen)").unwrap();
+        assert_eq!(
+            task.checklist().unwrap(),
+            vec![
+                ChecklistItem {
+                    text: "eggs (dozen)".to_string(),
+                    checked: false,
+                },
+                ChecklistItem {
+                    text: "milk".to_string(),
+                    checked: true,
+                },
+                ChecklistItem {
+                    text: "bread".to_string(),
+                    checked: false,
+                },
+            ]
+        );
+
+        let texts = || -> Vec<String> {
+            task.checklist()
+                .unwrap()
+                .into_iter()
+                .map(|item| item.text)
+                .collect()
+        };
+        task.move_checklist_item(2, 0).unwrap();
+        assert_eq!(texts(), vec!["bread", "eggs (dozen)", "milk"]);
+
+        task.remove_checklist_item(1).unwrap();
+        assert_eq!(texts(), vec!["bread", "milk"]);
+        assert_eq!(task.image().unwrap().checklist.len(), 2);
+        assert!(task.toggle_checklist_item(2).is_err());
+    }
+
+    #[test]
+    fn test_comments() {
+        let database = Database::new().unwrap();
+        let task = database.add_task().unwrap();
+        assert!(task.comments().unwrap().is_empty());
+
+        task.add_comment("first").unwrap();
+        task.add_comment("second").unwrap();
+        let texts: Vec<String> = task
+            .comments()
+            .unwrap()
+            .into_iter()
+            .map(|comment| comment.text)
+            .collect();
+        assert_eq!(texts, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_comments_concurrent() {
+        let database_a = Database::new().unwrap();
+        let task_a = database_a.add_task().unwrap();
+        task_a.add_comment("first").unwrap();
+
+        let database_b = Database::from_bytes(&database_a.to_bytes()).unwrap();
+        let task_b = database_b.list_tasks().unwrap().remove(0);
+        task_a.add_comment("from a").unwrap();
+        task_b.add_comment("from b").unwrap();
+
+        database_a
+            .apply_changes(database_b.get_changes(&[]).unwrap())
+            .unwrap();
+        database_b
+            .apply_changes(database_a.get_changes(&[]).unwrap())
+            .unwrap();
+
+        let comments = task_a.comments().unwrap();
+        assert_eq!(comments, task_b.comments().unwrap());
+        assert_eq!(comments.len(), 3);
+        assert_eq!(comments[0].text, "first");
+
+        let from_a = comments.iter().find(|c| c.text == "from a").unwrap();
+        let from_b = comments.iter().find(|c| c.text == "from b").unwrap();
+        assert_eq!(from_a.author, database_a.device);
+        assert_eq!(from_b.author, database_b.device);
+        assert_ne!(from_a.author, from_b.author);
+
+        // A later session on the same device has a new actor but the same author.
+        let database_c = Database::from_bytes(&database_a.to_bytes())
+            .unwrap()
+            .with_device(database_a.device.clone());
+        let task_c = database_c.list_tasks().unwrap().remove(0);
+        task_c.add_comment("from a again").unwrap();
+        let comments = task_c.comments().unwrap();
+        assert_eq!(comments.last().unwrap().author, from_a.author);
+    }
+
+    #[test]
+    fn test_custom_fields() {
+        let database = Database::new().unwrap();
+        database.define_field("points", FieldType::Number).unwrap();
+        database
+            .define_field(
+                "size",
+                FieldType::Enum(vec!["small".to_string(), "large".to_string()]),
+            )
+            .unwrap();
+        assert_eq!(
+            database.list_fields().unwrap(),
+            vec![
+                ("points".to_string(), FieldType::Number),
+                (
+                    "size".to_string(),
+                    FieldType::Enum(vec!["small".to_string(), "large".to_string()])
+                ),
+            ]
+        );
+
+        let task = database.add_task().unwrap();
+        assert_eq!(task.get_field("points").unwrap(), None);
+        task.set_field("points", FieldValue::Number(3.0)).unwrap();
+        task.set_field("size", FieldValue::Enum("large".to_string()))
+            .unwrap();
+        assert_eq!(
+            task.get_field("points").unwrap(),
+            Some(FieldValue::Number(3.0))
+        );
+
+        assert!(task
+            .set_field("points", FieldValue::String("3".to_string()))
+            .is_err());
+        assert!(task
+            .set_field("size", FieldValue::Enum("medium".to_string()))
+            .is_err());
+        assert!(task.set_field("owner", FieldValue::Boolean(true)).is_err());
+        assert!(task.get_field("owner").is_err());
+
+        // Changing a field's type hides values which no longer fit.
+        database.define_field("points", FieldType::Date).unwrap();
+        assert_eq!(task.get_field("points").unwrap(), None);
+        assert_eq!(
+            task.get_field("size").unwrap(),
+            Some(FieldValue::Enum("large".to_string()))
+        );
+
+        task.clear_field("size").unwrap();
+        assert_eq!(task.get_field("size").unwrap(), None);
+    }
+
+    #[test]
+    fn test_field_type_string_roundtrip() {
+        for field_type in [
+            FieldType::String,
+            FieldType::Number,
+            FieldType::Date,
+            FieldType::Enum(vec!["a".to_string(), "b".to_string()]),
+            FieldType::Boolean,
+        ] {
+            assert_eq!(
+                field_type.to_string().parse::<FieldType>().unwrap(),
+                field_type
+            );
+        }
+        assert!("float".parse::<FieldType>().is_err());
+    }
+
+    #[test]
+    fn test_projects() {
+        let database = Database::new().unwrap();
+        let work = database.add_project("work").unwrap();
+        let home = database.add_project("home").unwrap();
+        let names = || -> Vec<String> {
+            database
+                .list_projects()
+                .unwrap()
+                .iter()
+                .map(|project| project.name().unwrap())
+                .collect()
+        };
+        assert_eq!(names(), vec!["home", "work"]);
+
+        let task = database.add_task().unwrap();
+        assert!(task.project().unwrap().is_none());
+        task.set_project(Some(&work)).unwrap();
+        let subtask = task.add_subtask().unwrap();
+        database
+            .add_task()
+            .unwrap()
+            .set_project(Some(&home))
+            .unwrap();
+
+        assert_eq!(
+            task.project().unwrap().unwrap().id().unwrap(),
+            work.id().unwrap()
+        );
+        let ids = |tasks: Vec<Task>| -> Vec<Uuid> {
+            tasks.iter().map(|task| task.id().unwrap()).collect()
+        };
+        assert_eq!(
+            ids(work.tasks().unwrap()),
+            vec![subtask.id().unwrap(), task.id().unwrap()]
+        );
+        assert_eq!(home.tasks().unwrap().len(), 1);
+
+        work.set_description("Things for the day job").unwrap();
+        work.set_status(ProjectStatus::OnHold).unwrap();
+        work.archive().unwrap();
+        let work = database.get_project(work.id().unwrap()).unwrap().unwrap();
+        assert_eq!(work.description().unwrap(), "Things for the day job");
+        assert_eq!(work.status().unwrap(), ProjectStatus::OnHold);
+        assert!(work.archived().unwrap());
+        assert_eq!(work.tasks().unwrap().len(), 2);
+
+        task.set_project(None).unwrap();
+        assert_eq!(ids(work.tasks().unwrap()), vec![subtask.id().unwrap()]);
+    }
+
+    #[test]
+    fn test_clock() {
+        let database = Database::new().unwrap();
+        let project = database.add_project("work").unwrap();
+        let first = database.add_task().unwrap();
+        let second = database.add_task().unwrap();
+        first.set_project(Some(&project)).unwrap();
+        second.set_project(Some(&project)).unwrap();
+
+        let day = NaiveDate::from_ymd(2022, 11, 14);
+        let at = |hour: i64, minute: i64| {
+            local_midnight(day).unwrap() + Duration::hours(hour) + Duration::minutes(minute)
+        };
+
+        first.clock_in_at(at(9, 0)).unwrap();
+        first.clock_in_at(at(9, 10)).unwrap();
+        // Clocking into another task stops the running clock.
+        second.clock_in_at(at(9, 30)).unwrap();
+        assert_eq!(first.clock_entries().unwrap().len(), 1);
+        assert_eq!(first.clock_entries().unwrap()[0].end, Some(at(9, 30)));
+        let (running, entry) = database.running_clock().unwrap().unwrap();
+        assert_eq!(running.id().unwrap(), second.id().unwrap());
+        assert_eq!(entry.start, at(9, 30));
+
+        assert_eq!(
+            second.clocked_time(at(10, 0)).unwrap(),
+            Duration::minutes(30)
+        );
+        second.clock_out_at(at(10, 15)).unwrap();
+        assert!(database.running_clock().unwrap().is_none());
+        assert!(second.clock_out_at(at(10, 20)).is_err());
+
+        // Clocks which run past midnight are split between days.
+        first.clock_in_at(at(23, 0)).unwrap();
+        first.clock_out_at(at(25, 0)).unwrap();
+
+        let now = at(30, 0);
+        assert_eq!(first.clocked_time(now).unwrap(), Duration::minutes(150));
+        assert_eq!(second.clocked_time(now).unwrap(), Duration::minutes(45));
+        assert_eq!(project.clocked_time(now).unwrap(), Duration::minutes(195));
+        assert_eq!(
+            database.clocked_time_on(day, now).unwrap(),
+            Duration::minutes(135)
+        );
+        assert_eq!(
+            database
+                .clocked_time_on(day + Duration::days(1), now)
+                .unwrap(),
+            Duration::minutes(60)
+        );
+    }
+
+    #[test]
+    fn test_clock_restart() {
+        let dir = std::env::temp_dir().join(format!("tarsk-{}", Uuid::new_v4()));
+        let path = dir.join("tarsk.db");
+        let device = Database::device_id(&path).unwrap();
+        assert_eq!(Database::device_id(&path).unwrap(), device);
+
+        let database = Database::new().unwrap().with_device(device.clone());
+        let task = database.add_task().unwrap();
+        task.clock_in().unwrap();
+        database.save_incremental(&path).unwrap();
+
+        // The clock still belongs to us after a restart, even though the actor is new.
+        let database = Database::load(&path).unwrap().with_device(device);
+        let (task, _) = database.running_clock().unwrap().unwrap();
+        task.clock_out().unwrap();
+        assert!(database.running_clock().unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_clock_concurrent() {
+        let database_a = Database::new().unwrap();
+        let task_a = database_a.add_task().unwrap();
+        let database_b = Database::from_bytes(&database_a.to_bytes()).unwrap();
+        let task_b = database_b.list_tasks().unwrap().remove(0);
+
+        // Each peer has its own clock, so both keep running after syncing.
+        task_a.clock_in().unwrap();
+        task_b.clock_in().unwrap();
+        database_a
+            .apply_changes(database_b.get_changes(&[]).unwrap())
+            .unwrap();
+        database_b
+            .apply_changes(database_a.get_changes(&[]).unwrap())
+            .unwrap();
+        assert_eq!(task_a.clock_entries().unwrap().len(), 2);
+
+        task_a.clock_out().unwrap();
+        assert!(database_a.running_clock().unwrap().is_none());
+        let (running, _) = database_b.running_clock().unwrap().unwrap();
+        assert_eq!(running.id().unwrap(), task_a.id().unwrap());
+    }
+
+    #[test]
+    fn test_estimate_report() {
+        let database = Database::new().unwrap();
+        let project = database.add_project("work").unwrap();
+        let day = NaiveDate::from_ymd(2022, 11, 14);
+        let at = |hour: i64| local_midnight(day).unwrap() + Duration::hours(hour);
+
+        let clocked = database.add_task().unwrap();
+        clocked.set_project(Some(&project)).unwrap();
+        clocked.add_tag("docs").unwrap();
+        clocked.set_estimate(Some(Duration::hours(2))).unwrap();
+        assert_eq!(clocked.estimate().unwrap(), Some(Duration::hours(2)));
+        clocked.clock_in_at(at(9)).unwrap();
+        clocked.clock_out_at(at(12)).unwrap();
+
+        // Tasks which were never clocked fall back to how long it took to finish them.
+        let finished = database.add_task().unwrap();
+        finished.add_tag("docs").unwrap();
+        finished.add_tag("review").unwrap();
+        finished.set_estimate(Some(Duration::hours(1))).unwrap();
+        finished.set_status(Status::Done).unwrap();
+        assert!(finished.actual_time(at(0)).unwrap().is_some());
+
+        // Neither of these can be compared.
+        database
+            .add_task()
+            .unwrap()
+            .set_estimate(Some(Duration::hours(4)))
+            .unwrap();
+        let unestimated = database.add_task().unwrap();
+        unestimated.clock_in_at(at(9)).unwrap();
+        unestimated.clock_out_at(at(10)).unwrap();
+
+        let by_project = database.estimate_report(GroupBy::Project, at(13)).unwrap();
+        assert_eq!(by_project.len(), 2);
+        assert_eq!(by_project[0].group, None);
+        assert_eq!(by_project[0].tasks, 1);
+        assert_eq!(
+            by_project[1],
+            EstimateReport {
+                group: Some("work".to_string()),
+                tasks: 1,
+                estimated: Duration::hours(2),
+                actual: Duration::hours(3),
+            }
+        );
+
+        let by_tag = database.estimate_report(GroupBy::Tag, at(13)).unwrap();
+        let groups: Vec<Option<String>> = by_tag.iter().map(|row| row.group.clone()).collect();
+        assert_eq!(
+            groups,
+            vec![Some("docs".to_string()), Some("review".to_string())]
+        );
+        assert_eq!(by_tag[0].tasks, 2);
+        assert_eq!(by_tag[0].estimated, Duration::hours(3));
+
+        clocked.set_estimate(None).unwrap();
+        assert_eq!(clocked.estimate().unwrap(), None);
+    }
+
+    #[test]
+    fn test_blockers() {
+        let database = Database::new().unwrap();
+        let blocker = database.add_task().unwrap();
+        let blocked = database.add_task().unwrap();
+        blocked.add_blocker(&blocker).unwrap();
+
+        assert!(blocked.is_blocked().unwrap());
+        assert_eq!(
+            blocked.blockers().unwrap()[0].id().unwrap(),
+            blocker.id().unwrap()
+        );
+        assert!(blocker.add_blocker(&blocked).is_err());
+        assert!(blocker.add_blocker(&blocker).is_err());
+
+        let ids = |tasks: Vec<Task>| -> Vec<Uuid> {
+            tasks.iter().map(|task| task.id().unwrap()).collect()
+        };
+        assert_eq!(
+            ids(database.list_blocked_tasks().unwrap()),
+            vec![blocked.id().unwrap()]
+        );
+        assert_eq!(
+            ids(database.list_actionable_tasks().unwrap()),
+            vec![blocker.id().unwrap()]
+        );
+
+        blocker.set_status(Status::Done).unwrap();
+        assert!(!blocked.is_blocked().unwrap());
+        assert_eq!(
+            ids(database.list_actionable_tasks().unwrap()),
+            vec![blocked.id().unwrap()]
+        );
+
+        blocked.clear_blockers().unwrap();
+        assert!(blocked.blockers().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_blockers_concurrent_cycle() {
+        let database_a = Database::new().unwrap();
+        let id_x = database_a.add_task().unwrap().id().unwrap();
+        let id_y = database_a.add_task().unwrap().id().unwrap();
+        let database_b = Database::from_bytes(&database_a.to_bytes()).unwrap();
+
+        let x = database_a.get_task(id_x).unwrap().unwrap();
+        let y = database_a.get_task(id_y).unwrap().unwrap();
+        x.add_blocker(&y).unwrap();
+
+        let x = database_b.get_task(id_x).unwrap().unwrap();
+        let y = database_b.get_task(id_y).unwrap().unwrap();
+        y.add_blocker(&x).unwrap();
+
+        database_a
+            .apply_changes(database_b.get_changes(&[]).unwrap())
+            .unwrap();
+        database_b
+            .apply_changes(database_a.get_changes(&[]).unwrap())
+            .unwrap();
+
+        for database in [&database_a, &database_b] {
+            let blocked: Vec<Uuid> = database
+                .list_blocked_tasks()
+                .unwrap()
+                .iter()
+                .map(|task| task.id().unwrap())
+                .collect();
+            assert_eq!(blocked, vec![id_x.min(id_y)]);
+            assert_eq!(database.list_actionable_tasks().unwrap().len(), 1);
+        }
+
+        // The edge dropped to break the cycle mustn't come back once the other one is cleared.
+        let dropped = database_a.get_task(id_x.max(id_y)).unwrap().unwrap();
+        assert!(dropped.blockers().unwrap().is_empty());
+        dropped.clear_blockers().unwrap();
+        let kept = database_a.get_task(id_x.min(id_y)).unwrap().unwrap();
+        kept.clear_blockers().unwrap();
+        assert!(database_a.list_blocked_tasks().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_archive() {
+        let database = Database::new().unwrap();
+
+        let task = database.add_task().unwrap();
+        task.archive().unwrap();
+        assert!(task.archived().unwrap());
+        assert_eq!(database.list_tasks().unwrap().len(), 0);
+        assert_eq!(database.list_archived_tasks().unwrap().len(), 1);
+
+        task.unarchive().unwrap();
+        assert_eq!(database.list_tasks().unwrap().len(), 1);
+        assert_eq!(database.list_archived_tasks().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_archive_subtree() {
+        let database = Database::new().unwrap();
+        let parent = database.add_task().unwrap();
+        let child = parent.add_subtask().unwrap();
+        let grandchild = child.add_subtask().unwrap();
+        let other = database.add_task().unwrap();
+
+        // Archiving a task takes its subtasks with it rather than promoting them.
+        parent.archive().unwrap();
+        assert!(grandchild.archived().unwrap());
+        let tree = database.list_task_tree().unwrap();
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].1.id().unwrap(), other.id().unwrap());
+        assert_eq!(database.list_archived_tasks().unwrap().len(), 3);
+
+        // Unarchiving a subtask brings back the tasks it's nested under.
+        grandchild.unarchive().unwrap();
+        let depths: Vec<usize> = database
+            .list_task_tree()
+            .unwrap()
+            .iter()
+            .map(|(depth, _)| *depth)
+            .collect();
+        assert_eq!(depths.len(), 4);
+        assert!(depths.contains(&2));
+        assert!(database.list_archived_tasks().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_delete() {
+        let database = Database::new().unwrap();
+        database
+            .add_task()
+            .unwrap()
+            .splice_title(0, 0, "first")
+            .unwrap();
+        database
+            .add_task()
+            .unwrap()
+            .splice_title(0, 0, "second")
+            .unwrap();
+
+        let tasks = database.list_tasks().unwrap();
+        let second = tasks.into_iter().next().unwrap();
+        second.delete().unwrap();
+
+        let tasks = database.list_tasks().unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].title().unwrap(), "first");
+    }
+
+    #[test]
+    fn test_delete_concurrent_edit() {
+        let database_a = Database::new().unwrap();
+        database_a.add_task().unwrap();
+        let database_b = Database::from_bytes(&database_a.to_bytes()).unwrap();
+
+        database_a.list_tasks().unwrap().remove(0).delete().unwrap();
+        database_b.list_tasks().unwrap()[0]
+            .splice_title(0, 0, "edited")
+            .unwrap();
+
+        database_a
+            .apply_changes(database_b.get_changes(&[]).unwrap())
+            .unwrap();
+        database_b
+            .apply_changes(database_a.get_changes(&[]).unwrap())
+            .unwrap();
+
+        assert_eq!(database_a.list_tasks().unwrap().len(), 0);
+        assert_eq!(database_b.list_tasks().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_migrate_unversioned() {
+        let mut doc = AutoCommit::new();
+        doc.put_object(automerge::ROOT, "tasks", ObjType::List)
+            .unwrap();
+        let database = Database::from_bytes(&doc.save()).unwrap();
+
+        let doc = database.doc.lock().unwrap();
+        assert_eq!(get_schema_version(&doc).unwrap(), SCHEMA_VERSION);
+        assert!(doc.get(automerge::ROOT, "fields").unwrap().is_some());
+        assert!(doc.get(automerge::ROOT, "projects").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_migrate_concurrent() {
+        let mut doc = AutoCommit::new();
+        let tasks_id = doc
+            .put_object(automerge::ROOT, "tasks", ObjType::List)
+            .unwrap();
+        let legacy = doc.insert_object(&tasks_id, 0, ObjType::Map).unwrap();
+        doc.put(&legacy, "id", Uuid::new_v4().to_string()).unwrap();
+        doc.put(&legacy, "title", "legacy").unwrap();
+        let bytes = doc.save();
+
+        // Both peers migrate the same document before hearing from each other.
+        let database_a = Database::from_bytes(&bytes).unwrap();
+        let database_b = Database::from_bytes(&bytes).unwrap();
+        database_a
+            .define_field("points", FieldType::Number)
+            .unwrap();
+        database_b.add_project("work").unwrap();
+        let task_b = database_b.list_tasks().unwrap().remove(0);
+        task_b.splice_title(6, 0, " task").unwrap();
+
+        database_a
+            .apply_changes(database_b.get_changes(&[]).unwrap())
+            .unwrap();
+        database_b
+            .apply_changes(database_a.get_changes(&[]).unwrap())
+            .unwrap();
+        assert_eq!(database_a.get_heads(), database_b.get_heads());
+        for database in [&database_a, &database_b] {
+            assert_eq!(database.list_fields().unwrap().len(), 1);
+            assert_eq!(database.list_projects().unwrap().len(), 1);
+            let task = database.list_tasks().unwrap().remove(0);
+            assert_eq!(task.title().unwrap(), "legacy task");
+        }
+    }
+
+    #[test]
+    fn test_newer_schema() {
+        let database = Database::new().unwrap();
+        database.add_task().unwrap();
+
+        let newer = Database::from_bytes(&database.to_bytes()).unwrap();
+        newer
+            .doc
+            .lock()
+            .unwrap()
+            .put(automerge::ROOT, "schema_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        newer.add_task().unwrap();
+
+        assert!(matches!(
+            Database::from_bytes(&newer.to_bytes()),
+            Err(DatabaseError::SchemaMismatch { version }) if version == SCHEMA_VERSION + 1
+        ));
+
+        // Syncing with the newer peer is refused without merging anything.
+        let heads = database.get_heads();
+        assert!(database
+            .apply_changes(newer.get_changes(&[]).unwrap())
+            .is_err());
+        assert_eq!(database.get_heads(), heads);
+        assert_eq!(database.list_tasks().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_schema_version_concurrent() {
+        let older = Database::new().unwrap();
+        let newer = Database::from_bytes(&older.to_bytes()).unwrap();
+
+        // Both peers write the version concurrently,
+        // e.g. after each migrating the same unversioned document.
+        newer
+            .doc
+            .lock()
+            .unwrap()
+            .put(automerge::ROOT, "schema_version", SCHEMA_VERSION)
+            .unwrap();
+        older
+            .doc
+            .lock()
+            .unwrap()
+            .put(automerge::ROOT, "schema_version", 0u64)
+            .unwrap();
+        newer
+            .apply_changes(older.get_changes(&[]).unwrap())
+            .unwrap();
+
+        let doc = newer.doc.lock().unwrap();
+        assert_eq!(get_schema_version(&doc).unwrap(), SCHEMA_VERSION);
+        assert_eq!(
+            doc.get_all(automerge::ROOT, "schema_version")
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_diagnose_and_repair() {
+        let database = Database::new().unwrap();
+        let healthy = database.add_task().unwrap();
+        healthy.splice_title(0, 0, "healthy").unwrap();
+        {
+            // Tasks written by older versions may only have some of their structure.
+            let mut doc = database.doc.lock().unwrap();
+            let tasks_id = get_tasks_id(&doc).unwrap();
+            let legacy = doc.insert_object(&tasks_id, 1, ObjType::Map).unwrap();
+            doc.put(&legacy, "title", "legacy").unwrap();
+            let malformed = doc.insert_object(&tasks_id, 2, ObjType::Map).unwrap();
+            doc.put(&malformed, "id", Uuid::new_v4().to_string())
+                .unwrap();
+            doc.put(&malformed, "scheduled", 42).unwrap();
+        }
+
+        let tasks = database.list_tasks().unwrap();
+        assert_eq!(tasks.len(), 3);
+        let legacy = tasks
+            .iter()
+            .find(|task| task.title().unwrap() == "legacy")
+            .unwrap();
+        assert_eq!(legacy.body().unwrap(), "");
+        assert_eq!(legacy.image().unwrap().title, "legacy");
+
+        let diagnostics = database.diagnose().unwrap();
+        assert!(diagnostics.iter().all(|d| d.index != 0));
+        assert!(diagnostics
+            .iter()
+            .any(|d| d.index == 1 && d.problem == "Missing id" && d.repairable));
+        assert!(diagnostics.iter().any(|d| d.index == 2 && !d.repairable));
+
+        assert_eq!(database.repair().unwrap(), 2);
+        assert_eq!(database.repair().unwrap(), 0);
+        let diagnostics = database.diagnose().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].index, 2);
+
+        // The plain string title is turned into text by the migration on load.
+        let legacy = database.list_tasks().unwrap().remove(1);
+        assert!(legacy.id().is_ok());
+        assert!(matches!(
+            legacy.splice_title(6, 0, " task"),
+            Err(DatabaseError::Corrupt(_))
+        ));
+        let database = Database::from_bytes(&database.to_bytes()).unwrap();
+        let legacy = database.list_tasks().unwrap().remove(1);
+        legacy.splice_title(6, 0, " task").unwrap();
+        assert_eq!(legacy.title().unwrap(), "legacy task");
+    }
+
+    #[test]
+    fn test_concurrent_repair() {
+        let database = Database::new().unwrap();
+        {
+            let mut doc = database.doc.lock().unwrap();
+            let tasks_id = get_tasks_id(&doc).unwrap();
+            let legacy = doc.insert_object(&tasks_id, 0, ObjType::Map).unwrap();
+            let title_id = doc.put_object(&legacy, "title", ObjType::Text).unwrap();
+            doc.splice_text(&title_id, 0, 0, "legacy").unwrap();
+        }
+        let bytes = database.to_bytes();
+        let database_a = Database::from_bytes(&bytes).unwrap();
+        let database_b = Database::from_bytes(&bytes).unwrap();
+
+        // Both peers repair the task before hearing from each other.
+        assert_eq!(database_a.repair().unwrap(), 1);
+        assert_eq!(database_b.repair().unwrap(), 1);
+        let task_a = database_a.list_tasks().unwrap().remove(0);
+        task_a.add_tag("a").unwrap();
+        let task_b = database_b.list_tasks().unwrap().remove(0);
+        task_b.add_comment("b").unwrap();
+
+        database_a
+            .apply_changes(database_b.get_changes(&[]).unwrap())
+            .unwrap();
+        database_b
+            .apply_changes(database_a.get_changes(&[]).unwrap())
+            .unwrap();
+        assert_eq!(database_a.get_heads(), database_b.get_heads());
+        assert!(database_a.diagnose().unwrap().is_empty());
+        for database in [&database_a, &database_b] {
+            let task = database.list_tasks().unwrap().remove(0);
+            assert_eq!(task.id().unwrap(), task_a.id().unwrap());
+            assert_eq!(task.tags().unwrap(), vec!["a".to_string()]);
+            assert_eq!(task.comments().unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let database = Database::new().unwrap();
+        let task = database.add_task().unwrap();
+        database.define_field("points", FieldType::Number).unwrap();
+
+        assert!(matches!(
+            database.move_task(Uuid::new_v4(), 0),
+            Err(DatabaseError::NotFound(_))
+        ));
+        assert!(matches!(
+            database.get_changes(&[ChangeHash([1; 32])]),
+            Err(DatabaseError::NotFound(_))
+        ));
+        assert!(matches!(
+            task.set_field("points", FieldValue::Boolean(true)),
+            Err(DatabaseError::Invalid(_))
+        ));
+        assert!(matches!(
+            Database::from_bytes(b"not a database"),
+            Err(DatabaseError::Corrupt(_))
+        ));
+        assert!(matches!(
+            Database::from_bytes(b""),
+            Err(DatabaseError::Corrupt(_))
+        ));
+        assert!(matches!(
+            Database::load("/nonexistent/tarsk.db"),
+            Err(DatabaseError::Io(_))
+        ));
+
+        {
+            let mut doc = database.doc.lock().unwrap();
+            doc.put(&task.task_obj_id, "status", "someday").unwrap();
+        }
+        assert!(matches!(task.status(), Err(DatabaseError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_save_keeps_backup() {
+        let dir = std::env::temp_dir().join(format!("tarsk-{}", Uuid::new_v4()));
+        let path = dir.join("tarsk.db");
+
+        let database = Database::new().unwrap();
+        let first = database.add_task().unwrap().id().unwrap();
+        database.write_snapshot(&path).unwrap();
+        let second = database.add_task().unwrap().id().unwrap();
+        database.write_snapshot(&path).unwrap();
+        assert!(!with_suffix(&path, "tmp").exists());
+
+        let (loaded, fell_back) = Database::load_or_backup(&path).unwrap();
+        assert_eq!(loaded.list_tasks().unwrap().len(), 2);
+        assert!(fell_back.is_none());
+
+        // A torn write to the primary copy falls back to the previous save.
+        std::fs::write(&path, b"not a database").unwrap();
+        let (loaded, fell_back) = Database::load_or_backup(&path).unwrap();
+        assert!(matches!(fell_back, Some(DatabaseError::Corrupt(_))));
+        let tasks = loaded.list_tasks().unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].id().unwrap(), first);
+        assert_ne!(tasks[0].id().unwrap(), second);
+
+        // Once the unreadable copy is out of the way, edits are saved next to
+        // the new snapshot and the backup is never replaced by the unreadable copy.
+        let quarantined = Database::quarantine(&path).unwrap();
+        assert_eq!(std::fs::read(&quarantined).unwrap(), b"not a database");
+        loaded.save_incremental(&path).unwrap();
+        let third = loaded.add_task().unwrap().id().unwrap();
+        loaded.save_incremental(&path).unwrap();
+
+        let (reloaded, fell_back) = Database::load_or_backup(&path).unwrap();
+        assert!(fell_back.is_none());
+        assert!(reloaded.get_task(third).unwrap().is_some());
+        let backup = Database::load(with_suffix(&path, "bak")).unwrap();
+        assert_eq!(backup.list_tasks().unwrap().len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(
+            Database::load_or_backup(&path),
+            Err(DatabaseError::Io(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+    }
+
+    #[test]
+    fn test_save_incremental() {
+        let dir = std::env::temp_dir().join(format!("tarsk-{}", Uuid::new_v4()));
+        let path = dir.join("tarsk.db");
+        let log_path = with_suffix(&path, "log");
+
+        let database = Database::new().unwrap();
+        database.add_task().unwrap();
+        database.save_incremental(&path).unwrap();
+        assert!(path.exists());
+        assert!(!log_path.exists());
+        let snapshot = std::fs::read(&path).unwrap();
+
+        // Small edits only touch the log.
+        let task = database.add_task().unwrap();
+        task.splice_title(0, 0, "hello").unwrap();
+        database.save_incremental(&path).unwrap();
+        database.save_incremental(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), snapshot);
+        let log_len = std::fs::metadata(&log_path).unwrap().len();
+
+        let loaded = Database::load(&path).unwrap();
+        assert_eq!(loaded.list_tasks().unwrap().len(), 2);
+        assert_eq!(loaded.get_heads(), database.get_heads());
+
+        // Replayed changes aren't appended again.
+        loaded.save_incremental(&path).unwrap();
+        assert_eq!(std::fs::metadata(&log_path).unwrap().len(), log_len);
+
+        // A change torn off the end of the log is dropped.
+        let mut log = std::fs::read(&log_path).unwrap();
+        log.extend(&log[..log.len() / 2].to_vec());
+        std::fs::write(&log_path, &log).unwrap();
+        assert_eq!(
+            Database::load(&path).unwrap().list_tasks().unwrap().len(),
+            2
+        );
+
+        // Once the log outgrows the snapshot it's folded into it.
+        for _ in 0..20 {
+            database.add_task().unwrap();
+            database.save_incremental(&path).unwrap();
+        }
+        assert!(
+            !log_path.exists()
+                || std::fs::metadata(&log_path).unwrap().len()
+                    <= std::fs::metadata(&path).unwrap().len()
+        );
+        assert_eq!(
+            Database::load(&path).unwrap().list_tasks().unwrap().len(),
+            22
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recover_empty_file() {
+        let dir = std::env::temp_dir().join(format!("tarsk-{}", Uuid::new_v4()));
+        let path = dir.join("tarsk.db");
+
+        let database = Database::new().unwrap();
+        let task = database.add_task().unwrap().id().unwrap();
+        database.write_snapshot(&path).unwrap();
+        database.add_task().unwrap();
+        database.write_snapshot(&path).unwrap();
+
+        // What a save cut short by the old `File::create` and `write_all` leaves behind.
+        std::fs::write(&path, b"").unwrap();
+        let (_, fell_back) = Database::load_or_backup(&path).unwrap();
+        assert!(matches!(fell_back, Some(DatabaseError::Corrupt(_))));
+
+        let (recovered, recovery) = Database::recover(&path).unwrap();
+        assert_eq!(std::fs::read(&recovery.quarantined).unwrap(), b"");
+        assert_eq!(recovered.list_tasks().unwrap().len(), 1);
+        assert!(recovered.get_task(task).unwrap().is_some());
+        let backup = Database::load(with_suffix(&path, "bak")).unwrap();
+        assert_eq!(backup.list_tasks().unwrap().len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recover() {
+        let dir = std::env::temp_dir().join(format!("tarsk-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tarsk.db");
+
+        let database = Database::new().unwrap();
+        for title in ["one", "two", "three"] {
+            let task = database.add_task().unwrap();
+            task.splice_title(0, 0, title).unwrap();
+        }
+
+        // Every change, with garbage around them and the last one torn in half.
+        let changes = database.get_changes(&[]).unwrap();
+        let mut bytes = b"garbage".to_vec();
+        for change in &changes {
+            bytes.extend(change.raw_bytes());
+        }
+        bytes.extend(b"more garbage");
+        let heads = database.get_heads();
+        database.add_task().unwrap();
+        let torn = database.get_changes(&heads).unwrap();
+        let torn = torn[0].raw_bytes();
+        bytes.extend(&torn[..torn.len() / 2]);
+        std::fs::write(&path, &bytes).unwrap();
+
+        assert!(matches!(
+            Database::load(&path),
+            Err(DatabaseError::Corrupt(_))
+        ));
+        let (recovered, recovery) = Database::recover(&path).unwrap();
+        assert_eq!(recovery.changes, changes.len());
+        assert_eq!(std::fs::read(&recovery.quarantined).unwrap(), bytes);
+
+        let mut titles: Vec<String> = recovered
+            .list_tasks()
+            .unwrap()
+            .iter()
+            .map(|task| task.title().unwrap())
+            .collect();
+        titles.sort();
+        assert_eq!(titles, vec!["one", "three", "two"]);
+
+        // The recovered database replaces the unreadable one.
+        assert_eq!(
+            Database::load(&path).unwrap().get_heads(),
+            recovered.get_heads()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_quarantine_twice() {
+        let dir = std::env::temp_dir().join(format!("tarsk-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tarsk.db");
+        let log_path = with_suffix(&path, "log");
+
+        std::fs::write(&path, b"first").unwrap();
+        std::fs::write(&log_path, b"first log").unwrap();
+        let first = Database::quarantine(&path).unwrap();
+        std::fs::write(&path, b"second").unwrap();
+        std::fs::write(&log_path, b"second log").unwrap();
+        let second = Database::quarantine(&path).unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(std::fs::read(&first).unwrap(), b"first");
+        assert_eq!(
+            std::fs::read(with_suffix(&first, "log")).unwrap(),
+            b"first log"
+        );
+        assert_eq!(std::fs::read(&second).unwrap(), b"second");
+        assert_eq!(
+            std::fs::read(with_suffix(&second, "log")).unwrap(),
+            b"second log"
+        );
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_serialization_roundtrip() {
+        let bytes = {
+            let doc = Database::new().unwrap();
+            let task = doc.add_task().unwrap();
+            task.splice_title(0, 0, "hello world").unwrap();
+            task.set_scheduled(NaiveDate::from_ymd(2022, 11, 5)).unwrap();
+            doc.to_bytes()
+        };
+
+        let doc = Database::from_bytes(&bytes).unwrap();
+        let tasks = doc.list_tasks().unwrap();
+        assert_eq!(tasks.len(), 1);
+        let task = &tasks[0];
+        assert_eq!(task.title().unwrap(), "hello world");
+        assert_eq!(
+            task.scheduled().unwrap(),
+            Some(NaiveDate::from_ymd(2022, 11, 5))
+        );
     }
 }
@@ -1,9 +1,17 @@
+use std::collections::HashSet;
 use std::env;
+use std::io;
 use std::panic;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 
+use anyhow::anyhow;
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::Local;
+use chrono::Utc;
 use crossterm::event::Event;
 use crossterm::event::KeyCode;
 use crossterm::event::KeyEvent;
@@ -14,17 +22,33 @@ use tui::backend::CrosstermBackend;
 use tui::layout::Constraint;
 use tui::layout::Direction;
 use tui::layout::Layout;
+use tui::style::Color;
+use tui::style::Style;
+use tui::text::Span;
+use tui::text::Spans;
 use tui::widgets::Block;
 use tui::widgets::Borders;
 use tui::widgets::Paragraph;
 use tui::Terminal;
+use uuid::Uuid;
 
+use crate::database::DatabaseError;
+use crate::database::GroupBy;
+use crate::database::Priority;
+use crate::database::Project;
+use crate::database::Recurrence;
+use crate::database::SortOrder;
+use crate::database::Status;
+use crate::database::Task;
 use crate::database::TaskImage;
 
 mod controller;
 mod database;
 mod logging;
 
+/// How many days ahead of a deadline we start highlighting a task.
+const DUE_SOON_DAYS: i64 = 3;
+
 fn get_database_path() -> anyhow::Result<PathBuf> {
     let home_dir = PathBuf::from_str(&env::var("HOME")?)?;
     Ok(home_dir.join(".cache").join("tarsk.db"))
@@ -33,16 +57,49 @@ fn get_database_path() -> anyhow::Result<PathBuf> {
 #[tokio::main()]
 async fn main() -> anyhow::Result<()> {
     let db_path = get_database_path()?;
-    let db = Arc::new(match database::Database::load(db_path) {
-        Ok(db) => db,
-        Err(_) => database::Database::new()?,
-    });
-    let controller = controller::Controller::new(db.clone()).await?;
+
+    // Reports only read the database, so they shouldn't try to fix it either.
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("report") {
+        let group_by = match args.get(2) {
+            None => GroupBy::Project,
+            Some(group_by) => group_by.parse()?,
+        };
+        let (db, _) = database::Database::load_or_backup(&db_path)?;
+        print!("{}", format_estimate_report(&db, group_by, Utc::now())?);
+        return Ok(());
+    }
+
+    let mut startup_message = None;
+    let db = match database::Database::load_or_backup(&db_path) {
+        Ok((db, None)) => db,
+        Err(DatabaseError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+            database::Database::new()?
+        }
+        // Starting over would overwrite a file we just can't use.
+        Err(e @ (DatabaseError::Io(_) | DatabaseError::SchemaMismatch { .. })) => {
+            return Err(e.into())
+        }
+        // Even when the backup loads, the unreadable copy may have newer changes.
+        Ok((_, Some(e))) | Err(e) => {
+            let (db, recovery) = database::Database::recover(&db_path)?;
+            startup_message = Some(format!(
+                "Couldn't read the database ({}), so it was moved to {} and {} changes were recovered",
+                e,
+                recovery.quarantined.display(),
+                recovery.changes
+            ));
+            db
+        }
+    };
+    let db = Arc::new(db.with_device(database::Database::device_id(&db_path)?));
+
+    let controller = controller::Controller::new(db.clone(), get_database_path()?).await?;
 
     // This lets us re-establish normal terminal function when we panic! Nice!
     {
         let handler = panic::take_hook();
-        db.save::<&PathBuf>(&get_database_path()?)?;
+        db.save_incremental(&get_database_path()?)?;
         panic::set_hook(Box::new(move |panic_info| {
             let _ = disable_raw_mode();
             handler(panic_info)
@@ -52,36 +109,131 @@ async fn main() -> anyhow::Result<()> {
     print!("{}[2J", 27 as char);
 
     enable_raw_mode()?;
+    let result = run(&db, &controller, startup_message).await;
+
+    // Save even if the UI failed or the terminal is gone.
+    let restored = disable_raw_mode();
+    let shut_down = controller.shutdown().await;
+    result?;
+    shut_down?;
+    restored?;
+
+    Ok(())
+}
+
+async fn run(
+    db: &database::Database,
+    controller: &Arc<controller::Controller>,
+    startup_message: Option<String>,
+) -> anyhow::Result<()> {
     let stdout = std::io::stdout();
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
     let mut state = State::new();
+    state.message = startup_message;
     loop {
-        let tasks: Vec<TaskImage> = db
-            .list_tasks()?
+        let rows: Vec<(Row, TaskImage)> = state
+            .visible_rows(db)?
             .into_iter()
-            .flat_map(|task| task.image())
+            .map(|row| {
+                // Keep unreadable tasks so that rows still line up with `current_task`.
+                let image = row.task.image().unwrap_or_else(|e| TaskImage {
+                    title: format!("(unreadable: {})", e),
+                    ..TaskImage::default()
+                });
+                (row, image)
+            })
             .collect();
+        let problems = db.diagnose()?;
+        let problem_count = if problems.is_empty() {
+            "".to_string()
+        } else {
+            format!(" [{} problems, R to repair]", problems.len())
+        };
+
+        let today = Local::today().naive_local();
+        let overdue = task_ids(db.list_overdue_tasks(today)?);
+        let due_soon = task_ids(db.list_tasks_due_within(today, DUE_SOON_DAYS)?);
+        let blocked = task_ids(db.list_blocked_tasks()?);
 
-        let task_titles = tasks
+        let task_titles = rows
             .iter()
             .enumerate()
-            .map(|(i, task)| {
+            .map(|(i, (row, task))| {
                 let mut title = task.title.as_str();
                 if title.is_empty() {
                     title = "(No Title)";
                 }
 
-                if i == state.current_task {
-                    format!("> {}", title)
+                let checkbox = match task.status {
+                    Status::Todo => "[ ]",
+                    Status::InProgress => "[~]",
+                    Status::Done => "[x]",
+                    Status::Cancelled => "[-]",
+                };
+
+                let fold = if !row.has_subtasks {
+                    "  "
+                } else if state.is_collapsed(&row.task) {
+                    "▸ "
                 } else {
-                    format!("  {}", title)
-                }
-            })
-            .collect::<Vec<String>>()
-            .join("\n");
+                    "▾ "
+                };
+                let indent = "  ".repeat(row.depth);
+
+                let priority = match task.priority {
+                    None => "".to_string(),
+                    Some(priority) => format!("[#{}] ", priority),
+                };
+
+                let progress = if task.checklist.is_empty() {
+                    "".to_string()
+                } else {
+                    let checked = task.checklist.iter().filter(|item| item.checked).count();
+                    format!(" {}/{}", checked, task.checklist.len())
+                };
 
+                let tags = if task.tags.is_empty() {
+                    "".to_string()
+                } else {
+                    format!(" :{}:", task.tags.join(":"))
+                };
+
+                let id = row.task.id().ok();
+                let blocked_marker = if id.is_some_and(|id| blocked.contains(&id)) {
+                    "(blocked) "
+                } else {
+                    ""
+                };
+
+                let line = if i == state.current_task {
+                    format!(
+                        "> {}{}{} {}{}{}{}{}",
+                        indent, fold, checkbox, blocked_marker, priority, title, progress, tags
+                    )
+                } else {
+                    format!(
+                        "  {}{}{} {}{}{}{}{}",
+                        indent, fold, checkbox, blocked_marker, priority, title, progress, tags
+                    )
+                };
+
+                let style = if id.is_some_and(|id| overdue.contains(&id)) {
+                    Style::default().fg(Color::Red)
+                } else if id.is_some_and(|id| due_soon.contains(&id)) {
+                    Style::default().fg(Color::Yellow)
+                } else {
+                    Style::default()
+                };
+                Spans::from(Span::styled(line, style))
+            })
+            .collect::<Vec<Spans>>();
+        let (rows, tasks): (Vec<Row>, Vec<TaskImage>) = rows.into_iter().unzip();
+        let prompt = match &state.prompt {
+            None => "".to_string(),
+            Some(prompt) => prompt.render(db)?,
+        };
         let (current_title, current_contents) =
             if let Some(current_task) = tasks.get(state.current_task) {
                 (current_task.title.as_str(), current_task.body.as_str())
@@ -89,6 +241,172 @@ async fn main() -> anyhow::Result<()> {
                 ("No Task", "")
             };
 
+        let current_scheduled = match tasks.get(state.current_task) {
+            Some(TaskImage {
+                scheduled: Some(scheduled),
+                ..
+            }) => {
+                let conflicts = rows[state.current_task].task.scheduled_conflicts()?;
+                if conflicts.len() > 1 {
+                    format!(" (scheduled {}, conflicting)", scheduled)
+                } else {
+                    format!(" (scheduled {})", scheduled)
+                }
+            }
+            _ => "".to_string(),
+        };
+
+        let current_deadline = match tasks.get(state.current_task) {
+            Some(TaskImage {
+                deadline: Some(deadline),
+                ..
+            }) => format!(" (deadline {})", deadline),
+            _ => "".to_string(),
+        };
+
+        let current_recurrence = match tasks.get(state.current_task) {
+            Some(TaskImage {
+                recurrence: Some(recurrence),
+                ..
+            }) => format!(" (repeats {})", recurrence),
+            _ => "".to_string(),
+        };
+
+        let current_completed = match tasks.get(state.current_task) {
+            Some(TaskImage {
+                status,
+                completed_at: Some(completed_at),
+                ..
+            }) => format!(
+                " ({} {})",
+                status,
+                completed_at.with_timezone(&Local).date_naive()
+            ),
+            _ => "".to_string(),
+        };
+
+        let current_blockers = match rows.get(state.current_task) {
+            Some(row) if row.task.is_blocked()? => {
+                let mut titles = vec![];
+                for blocker in row.task.blockers()? {
+                    if !blocker.status()?.is_finished() {
+                        titles.push(blocker.title()?);
+                    }
+                }
+                format!(" (blocked by {})", titles.join(", "))
+            }
+            _ => "".to_string(),
+        };
+
+        let now = Utc::now();
+        let current_clocked = match rows.get(state.current_task) {
+            None => "".to_string(),
+            Some(row) => {
+                let clocked = row.task.clocked_time(now)?;
+                match row.task.estimate()? {
+                    None if clocked.is_zero() => "".to_string(),
+                    None => format!(" (clocked {})", format_duration(clocked)),
+                    Some(estimate) => format!(
+                        " (clocked {} of {})",
+                        format_duration(clocked),
+                        format_duration(estimate)
+                    ),
+                }
+            }
+        };
+        let clock_indicator = match db.running_clock()? {
+            None => "".to_string(),
+            Some((task, entry)) => format!(
+                " [clocked into {} for {}, {} today]",
+                task.title()?,
+                format_duration(entry.duration(now)),
+                format_duration(db.clocked_time_on(today, now)?),
+            ),
+        };
+
+        let current_checklist = match tasks.get(state.current_task) {
+            None => vec![],
+            Some(current_task) => current_task.checklist.clone(),
+        };
+        let checklist_lines = current_checklist
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let checkbox = if item.checked { "[x]" } else { "[ ]" };
+                if state.mode == EditMode::Checklist && i == state.current_item {
+                    format!("> {} {}", checkbox, item.text)
+                } else {
+                    format!("  {} {}", checkbox, item.text)
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        let checked_count = current_checklist.iter().filter(|item| item.checked).count();
+
+        let mut current_fields = vec![];
+        if let Some(row) = rows.get(state.current_task) {
+            for (name, _) in db.list_fields()? {
+                if let Some(value) = row.task.get_field(&name)? {
+                    current_fields.push((name, value));
+                }
+            }
+        }
+        let field_lines = current_fields
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let current_comments = match rows.get(state.current_task) {
+            None => vec![],
+            Some(row) => row.task.comments()?,
+        };
+        let comment_lines = current_comments
+            .iter()
+            .map(|comment| {
+                format!(
+                    "{} {}: {}",
+                    comment
+                        .created_at
+                        .with_timezone(&Local)
+                        .format("%Y-%m-%d %H:%M"),
+                    &comment.author[..comment.author.len().min(8)],
+                    comment.text
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let current_project = state.current_project(db)?;
+        let mut project_lines = vec![format!(
+            "{} All tasks",
+            if current_project.is_none() { ">" } else { " " }
+        )];
+        for project in state.visible_projects(db)? {
+            let id = project.id()?;
+            project_lines.push(format!(
+                "{} {} [{}{}] ({}, {})",
+                if state.project == Some(id) { ">" } else { " " },
+                project.name()?,
+                project.status()?,
+                if project.archived()? {
+                    ", archived"
+                } else {
+                    ""
+                },
+                project.tasks()?.len(),
+                format_duration(project.clocked_time(now)?),
+            ));
+        }
+        if let Some(project) = &current_project {
+            let description = project.description()?;
+            if !description.is_empty() {
+                project_lines.push(format!("  {}", description));
+            }
+        }
+        let projects_height = project_lines.len() as u16 + 2;
+        let project_lines = project_lines.join("\n");
+
         terminal.draw(|f| {
             let chunks = Layout::default()
                 .direction(Direction::Horizontal)
@@ -97,23 +415,56 @@ async fn main() -> anyhow::Result<()> {
 
             let right_chunks = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Length(3), Constraint::Percentage(100)].as_ref())
+                .constraints(
+                    [
+                        Constraint::Length(3),
+                        Constraint::Length(if current_fields.is_empty() {
+                            0
+                        } else {
+                            current_fields.len() as u16 + 2
+                        }),
+                        Constraint::Percentage(50),
+                        Constraint::Percentage(25),
+                        Constraint::Percentage(25),
+                    ]
+                    .as_ref(),
+                )
                 .split(chunks[1]);
 
-            let task_list_chunk = chunks[0];
+            let left_chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(
+                    [
+                        Constraint::Length(projects_height),
+                        Constraint::Percentage(100),
+                    ]
+                    .as_ref(),
+                )
+                .split(chunks[0]);
+
+            let projects_chunk = left_chunks[0];
+            let task_list_chunk = left_chunks[1];
             let title_chunk = right_chunks[0];
-            let body_chunk = right_chunks[1];
+            let fields_chunk = right_chunks[1];
+            let body_chunk = right_chunks[2];
+            let checklist_chunk = right_chunks[3];
+            let comments_chunk = right_chunks[4];
 
             let task_list = Paragraph::new(task_titles).block(
                 Block::default()
                     .title(format!(
-                        "{}Tasks ({})",
+                        "{}{}Tasks ({}){}{}{}{}",
                         if state.mode == EditMode::List {
                             "* "
                         } else {
                             ""
                         },
-                        tasks.len()
+                        if state.show_archived { "Archived " } else { "" },
+                        rows.len(),
+                        problem_count,
+                        clock_indicator,
+                        state.list_annotations(),
+                        prompt,
                     ))
                     .borders(Borders::ALL),
             );
@@ -121,12 +472,18 @@ async fn main() -> anyhow::Result<()> {
             let task_title = Paragraph::new(current_title).block(
                 Block::default()
                     .title(format!(
-                        "{}Title",
+                        "{}Title{}{}{}{}{}{}",
                         if state.mode == EditMode::Title {
                             "* "
                         } else {
                             ""
-                        }
+                        },
+                        current_scheduled,
+                        current_deadline,
+                        current_recurrence,
+                        current_completed,
+                        current_blockers,
+                        current_clocked,
                     ))
                     .borders(Borders::ALL),
             );
@@ -144,37 +501,403 @@ async fn main() -> anyhow::Result<()> {
                     .borders(Borders::ALL),
             );
 
+            let task_checklist = Paragraph::new(checklist_lines).block(
+                Block::default()
+                    .title(format!(
+                        "{}Checklist ({}/{})",
+                        if state.mode == EditMode::Checklist {
+                            "* "
+                        } else {
+                            ""
+                        },
+                        checked_count,
+                        current_checklist.len(),
+                    ))
+                    .borders(Borders::ALL),
+            );
+
+            let project_list = Paragraph::new(project_lines)
+                .block(Block::default().title("Projects").borders(Borders::ALL));
+
+            f.render_widget(project_list, projects_chunk);
             f.render_widget(task_list, task_list_chunk);
             f.render_widget(task_title, title_chunk);
+            let task_fields = Paragraph::new(field_lines)
+                .block(Block::default().title("Fields").borders(Borders::ALL));
+
+            f.render_widget(task_fields, fields_chunk);
             f.render_widget(task_body, body_chunk);
+            let task_comments = Paragraph::new(comment_lines).block(
+                Block::default()
+                    .title(format!("Comments ({})", current_comments.len()))
+                    .borders(Borders::ALL),
+            );
+
+            f.render_widget(task_checklist, checklist_chunk);
+            f.render_widget(task_comments, comments_chunk);
         })?;
 
         let event = controller.get_event().await;
-        if let controller::Event::Terminal(Event::Key(key)) = event {
-            if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
-                break;
+        match event {
+            controller::Event::Shutdown => break,
+            controller::Event::Terminal(Event::Key(key))
+                if key.modifiers.contains(KeyModifiers::CONTROL)
+                    && key.code == KeyCode::Char('c') =>
+            {
+                break
             }
+            _ => {}
         }
-        state = state.handle_event(&db, event)?;
+        state = state.handle_event(db, event)?;
     }
 
-    disable_raw_mode()?;
-    db.save::<&PathBuf>(&get_database_path()?)?;
-
     Ok(())
 }
 
+/// Keeps the tasks of a tree which belong to the given project.
+fn filter_project<'a>(
+    tree: Vec<(usize, Task<'a>)>,
+    project_id: Uuid,
+) -> anyhow::Result<Vec<(usize, Task<'a>)>> {
+    let mut ancestors: Vec<(usize, bool)> = vec![];
+    let mut filtered = vec![];
+    for (depth, task) in tree {
+        while ancestors
+            .last()
+            .is_some_and(|(ancestor, _)| *ancestor >= depth)
+        {
+            ancestors.pop();
+        }
+        let in_project = match task.project()? {
+            None => false,
+            Some(project) => project.id()? == project_id,
+        };
+        if in_project {
+            let kept_ancestors = ancestors.iter().filter(|(_, kept)| *kept).count();
+            filtered.push((kept_ancestors, task));
+        }
+        ancestors.push((depth, in_project));
+    }
+    Ok(filtered)
+}
+
+/// Formats a duration as hours and minutes, like org-mode's clock tables.
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.num_minutes();
+    let sign = if minutes < 0 { "-" } else { "" };
+    let minutes = minutes.abs();
+    format!("{}{}:{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// Parses durations such as `1h30m`, `45m`, `2h` or `1:30`.
+fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    if let Some((hours, minutes)) = s.split_once(':') {
+        let hours: u32 = hours.parse()?;
+        let minutes: u32 = minutes.parse()?;
+        return Ok(Duration::hours(hours.into()) + Duration::minutes(minutes.into()));
+    }
+
+    let mut duration = Duration::zero();
+    let mut number = String::new();
+    for c in s.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'h' => duration = duration + Duration::hours(number.parse()?),
+            'm' => duration = duration + Duration::minutes(number.parse()?),
+            _ => return Err(anyhow!("Can't parse duration `{}`", s)),
+        }
+        if !c.is_ascii_digit() {
+            number.clear();
+        }
+    }
+    if !number.is_empty() {
+        return Err(anyhow!("Missing unit in duration `{}`", s));
+    }
+    Ok(duration)
+}
+
+/// Formats estimated against actual time for `tarsk report [tag|project]`.
+fn format_estimate_report(
+    db: &database::Database,
+    group_by: GroupBy,
+    now: DateTime<Utc>,
+) -> anyhow::Result<String> {
+    let mut report = format!(
+        "{:<24} {:>6} {:>9} {:>9} {:>9}\n",
+        group_by.to_string(),
+        "tasks",
+        "estimate",
+        "actual",
+        "over"
+    );
+    for row in db.estimate_report(group_by, now)? {
+        report += &format!(
+            "{:<24} {:>6} {:>9} {:>9} {:>9}\n",
+            row.group.as_deref().unwrap_or("(none)"),
+            row.tasks,
+            format_duration(row.estimated),
+            format_duration(row.actual),
+            format_duration(row.actual - row.estimated),
+        );
+    }
+    Ok(report)
+}
+
+fn task_ids(tasks: Vec<Task>) -> HashSet<Uuid> {
+    tasks.iter().flat_map(|task| task.id()).collect()
+}
+
 struct State {
     current_task: usize,
+    current_item: usize,
+    selected_task: Option<Uuid>,
     mode: EditMode,
+    hide_finished: bool,
+    show_archived: bool,
+    confirm_delete: bool,
+    collapsed: HashSet<Uuid>,
+    tag_filter: Option<String>,
+    project: Option<Uuid>,
+    sort: SortOrder,
+    actionable_only: bool,
+    pending_blocked: Option<Uuid>,
+    message: Option<String>,
+    prompt: Option<Prompt>,
+}
+
+/// A task as it is displayed in the task list.
+struct Row<'a> {
+    depth: usize,
+    has_subtasks: bool,
+    task: Task<'a>,
 }
 
 impl State {
     fn new() -> Self {
         Self {
             current_task: 0,
+            current_item: 0,
+            selected_task: None,
             mode: EditMode::List,
+            hide_finished: false,
+            show_archived: false,
+            confirm_delete: false,
+            collapsed: HashSet::new(),
+            tag_filter: None,
+            project: None,
+            sort: SortOrder::Manual,
+            actionable_only: false,
+            pending_blocked: None,
+            message: None,
+            prompt: None,
+        }
+    }
+
+    /// Lists the tasks in the task list, which `current_task` indexes into.
+    fn visible_tasks<'a>(&self, db: &'a database::Database) -> anyhow::Result<Vec<Task<'a>>> {
+        Ok(self
+            .visible_rows(db)?
+            .into_iter()
+            .map(|row| row.task)
+            .collect())
+    }
+
+    fn list_annotations(&self) -> String {
+        let mut annotations = String::new();
+        if self.sort != SortOrder::Manual {
+            annotations += &format!(" by {}", self.sort);
+        }
+        if self.hide_finished {
+            annotations += " [hiding finished]";
+        }
+        if self.actionable_only {
+            annotations += " [actionable]";
+        }
+        if let Some(tag) = &self.tag_filter {
+            annotations += &format!(" [tagged {}]", tag);
+        }
+        if self.confirm_delete {
+            annotations += " Delete task? (y/n)";
+        }
+        if self.pending_blocked.is_some() {
+            annotations += " Select the blocking task (b)";
+        }
+        if let Some(message) = &self.message {
+            annotations += &format!(" {}", message);
+        }
+        annotations
+    }
+
+    fn visible_rows<'a>(&self, db: &'a database::Database) -> anyhow::Result<Vec<Row<'a>>> {
+        // A task's parent may not be next to it in filtered and sorted listings.
+        let tree = if self.tag_filter.is_none()
+            && !self.show_archived
+            && !self.actionable_only
+            && self.sort == SortOrder::Manual
+        {
+            db.list_task_tree()?
+        } else {
+            let tasks = match (&self.tag_filter, self.show_archived) {
+                (None, false) => db.list_tasks_sorted(self.sort)?,
+                (None, true) => self.sort.sort(db.list_archived_tasks()?)?,
+                (Some(tag), false) => self.sort.sort(db.list_tasks_with_tag(tag)?)?,
+                (Some(tag), true) => {
+                    let mut tasks = vec![];
+                    for task in db.list_archived_tasks()? {
+                        if task.tags()?.contains(tag) {
+                            tasks.push(task);
+                        }
+                    }
+                    self.sort.sort(tasks)?
+                }
+            };
+
+            let actionable = if self.actionable_only {
+                Some(task_ids(db.list_actionable_tasks()?))
+            } else {
+                None
+            };
+            tasks
+                .into_iter()
+                .filter(|task| match &actionable {
+                    None => true,
+                    Some(actionable) => task.id().is_ok_and(|id| actionable.contains(&id)),
+                })
+                .map(|task| (0, task))
+                .collect()
+        };
+        let tree = match self.project {
+            None => tree,
+            Some(project_id) => filter_project(tree, project_id)?,
+        };
+        let depths: Vec<usize> = tree.iter().map(|(depth, _)| *depth).collect();
+
+        // Hiding a task also hides everything nested underneath it.
+        let mut rows = vec![];
+        let mut hidden_below = None;
+        for (i, (depth, task)) in tree.into_iter().enumerate() {
+            if let Some(hidden_depth) = hidden_below {
+                if depth > hidden_depth {
+                    continue;
+                }
+                hidden_below = None;
+            }
+
+            if self.hide_finished && task.status()?.is_finished() {
+                hidden_below = Some(depth);
+                continue;
+            }
+
+            let has_subtasks = depths.get(i + 1).is_some_and(|next| *next > depth);
+            if has_subtasks && self.is_collapsed(&task) {
+                hidden_below = Some(depth);
+            }
+            rows.push(Row {
+                depth,
+                has_subtasks,
+                task,
+            });
+        }
+        Ok(rows)
+    }
+
+    /// Lists the projects which can be switched to.
+    fn visible_projects<'a>(&self, db: &'a database::Database) -> anyhow::Result<Vec<Project<'a>>> {
+        let mut projects = vec![];
+        for project in db.list_projects()? {
+            if self.show_archived || !project.archived()? {
+                projects.push(project);
+            }
+        }
+        Ok(projects)
+    }
+
+    fn current_project<'a>(
+        &self,
+        db: &'a database::Database,
+    ) -> anyhow::Result<Option<Project<'a>>> {
+        match self.project {
+            None => Ok(None),
+            Some(project_id) => Ok(db.get_project(project_id)?),
+        }
+    }
+
+    /// Switches to the next (or previous) project, passing through all tasks after the last.
+    fn switch_project(&mut self, db: &database::Database, forward: bool) -> anyhow::Result<()> {
+        let mut choices = vec![None];
+        for project in self.visible_projects(db)? {
+            choices.push(Some(project.id()?));
+        }
+        let index = choices
+            .iter()
+            .position(|choice| *choice == self.project)
+            .unwrap_or(0);
+        let index = if forward {
+            (index + 1) % choices.len()
+        } else {
+            (index + choices.len() - 1) % choices.len()
+        };
+        self.project = choices[index];
+        self.current_task = 0;
+        Ok(())
+    }
+
+    fn is_collapsed(&self, task: &Task) -> bool {
+        match task.id() {
+            Err(_) => false,
+            Ok(id) => self.collapsed.contains(&id),
+        }
+    }
+
+    /// Points `current_task` at the task with the given ID, if it's visible.
+    fn select(&mut self, db: &database::Database, id: Uuid) -> anyhow::Result<()> {
+        if let Some(index) = self
+            .visible_tasks(db)?
+            .iter()
+            .position(|task| task.id().ok() == Some(id))
+        {
+            self.current_task = index;
+        }
+        Ok(())
+    }
+
+    /// Moves `current_task` to wherever peers' changes have moved the selected task.
+    fn restore_selection(&mut self, db: &database::Database) -> anyhow::Result<()> {
+        let selected_task = match self.selected_task {
+            None => return Ok(()),
+            Some(selected_task) => selected_task,
+        };
+
+        let tasks = self.visible_tasks(db)?;
+        if let Some(index) = tasks
+            .iter()
+            .position(|task| task.id().ok() == Some(selected_task))
+        {
+            self.current_task = index;
+        } else if self.current_task >= tasks.len() {
+            self.current_task = tasks.len().saturating_sub(1);
         }
+        Ok(())
+    }
+
+    /// Looks the selected task up by ID so that concurrent inserts can't change it.
+    fn selected<'a>(&self, db: &'a database::Database) -> anyhow::Result<Option<Task<'a>>> {
+        if let Some(selected_task) = self.selected_task {
+            if let Some(task) = db.get_task(selected_task)? {
+                return Ok(Some(task));
+            }
+        }
+
+        // Tasks created before IDs existed can only be found by position.
+        Ok(self.visible_tasks(db)?.into_iter().nth(self.current_task))
+    }
+
+    fn remember_selection(&mut self, db: &database::Database) -> anyhow::Result<()> {
+        self.selected_task = self
+            .visible_tasks(db)?
+            .get(self.current_task)
+            .and_then(|task| task.id().ok());
+        Ok(())
     }
 
     fn handle_event(
@@ -182,20 +905,217 @@ impl State {
         db: &database::Database,
         event: controller::Event,
     ) -> anyhow::Result<Self> {
+        self.restore_selection(db)?;
+
         if let controller::Event::Terminal(Event::Key(key)) = event {
-            if key.code == KeyCode::BackTab {
-                self.mode = self.mode.prev();
-            } else if key.code == KeyCode::Tab {
-                self.mode = self.mode.next();
-            }
+            self.message = None;
+
+            let handled = if self.prompt.is_some() {
+                self.handle_event_prompt(db, key)
+            } else {
+                if key.code == KeyCode::BackTab {
+                    self.mode = self.mode.prev();
+                } else if key.code == KeyCode::Tab {
+                    self.mode = self.mode.next();
+                }
 
-            // TODO: i hate that this has to have a heap allocation every call :(
-            let handler = self.mode.handler();
-            handler(&mut self, db, key)?;
+                // TODO: i hate that this has to have a heap allocation every call :(
+                let handler = self.mode.handler();
+                handler(&mut self, db, key)
+            };
+
+            // Legacy tasks without an id are still listed, so errors are shown rather than quitting.
+            if let Err(e) = handled {
+                self.message = Some(match e.downcast_ref::<DatabaseError>() {
+                    Some(e @ DatabaseError::Corrupt(_)) => format!("{}, press R to repair", e),
+                    _ => e.to_string(),
+                });
+            }
         }
 
+        self.remember_selection(db)?;
         Ok(self)
     }
+
+    fn handle_event_prompt(
+        &mut self,
+        db: &database::Database,
+        event: KeyEvent,
+    ) -> anyhow::Result<()> {
+        let prompt = match &mut self.prompt {
+            None => return Ok(()),
+            Some(prompt) => prompt,
+        };
+
+        match event.code {
+            KeyCode::Char(c) => prompt.input.push(c),
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            }
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Enter => {
+                let prompt = self.prompt.take().unwrap();
+                let input = prompt.input.trim();
+                match prompt.kind {
+                    PromptKind::TagFilter => {
+                        self.tag_filter = if input.is_empty() {
+                            None
+                        } else {
+                            Some(input.to_string())
+                        };
+                        self.current_task = 0;
+                    }
+                    PromptKind::DefineField if !input.is_empty() => {
+                        if let Err(e) = define_field(db, input) {
+                            self.message = Some(e.to_string());
+                        }
+                    }
+                    PromptKind::SetField if !input.is_empty() => {
+                        if let Some(task) = self.selected(db)? {
+                            if let Err(e) = set_field(db, &task, input) {
+                                self.message = Some(e.to_string());
+                            }
+                        }
+                    }
+                    PromptKind::AddProject if !input.is_empty() => {
+                        self.project = Some(db.add_project(input)?.id()?);
+                        self.current_task = 0;
+                    }
+                    PromptKind::DescribeProject => {
+                        if let Some(project) = self.current_project(db)? {
+                            project.set_description(input)?;
+                        }
+                    }
+                    PromptKind::Estimate => {
+                        if let Some(task) = self.selected(db)? {
+                            // An empty estimate clears it.
+                            match input {
+                                "" => task.set_estimate(None)?,
+                                input => match parse_duration(input) {
+                                    Ok(estimate) => task.set_estimate(Some(estimate))?,
+                                    Err(e) => self.message = Some(e.to_string()),
+                                },
+                            }
+                        }
+                    }
+                    PromptKind::AddComment if !input.is_empty() => {
+                        if let Some(task) = self.selected(db)? {
+                            task.add_comment(input)?;
+                        }
+                    }
+                    PromptKind::AddTag | PromptKind::RemoveTag if !input.is_empty() => {
+                        if let Some(task) = self.selected(db)? {
+                            if prompt.kind == PromptKind::AddTag {
+                                task.add_tag(input)?;
+                            } else {
+                                task.remove_tag(input)?;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Declares a custom field from input such as `points:number` or `size:enum:s,m,l`.
+fn define_field(db: &database::Database, input: &str) -> anyhow::Result<()> {
+    let (name, field_type) = input
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Expected name:type"))?;
+    db.define_field(name.trim(), field_type.trim().parse()?)?;
+    Ok(())
+}
+
+/// Sets a custom field from input such as `points=3`, or clears it for `points=`.
+fn set_field(db: &database::Database, task: &Task, input: &str) -> anyhow::Result<()> {
+    let (name, value) = input
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected name=value"))?;
+    let (name, value) = (name.trim(), value.trim());
+    if value.is_empty() {
+        task.clear_field(name)?;
+        return Ok(());
+    }
+    let field_type = db
+        .list_fields()?
+        .into_iter()
+        .find(|(field, _)| field == name)
+        .map(|(_, field_type)| field_type)
+        .ok_or_else(|| anyhow!("Unknown field `{}`", name))?;
+    task.set_field(name, field_type.parse_value(value)?)?;
+    Ok(())
+}
+
+#[derive(Eq, PartialEq)]
+enum PromptKind {
+    TagFilter,
+    AddTag,
+    RemoveTag,
+    DefineField,
+    SetField,
+    AddComment,
+    AddProject,
+    DescribeProject,
+    Estimate,
+}
+
+/// A line of text being entered by the user.
+struct Prompt {
+    kind: PromptKind,
+    input: String,
+}
+
+impl Prompt {
+    fn new(kind: PromptKind) -> Self {
+        Self {
+            kind,
+            input: String::new(),
+        }
+    }
+
+    fn render(&self, db: &database::Database) -> anyhow::Result<String> {
+        let (label, suggestions) = match self.kind {
+            PromptKind::TagFilter => ("Filter by tag", db.list_tags()?),
+            PromptKind::AddTag => ("Add tag", db.list_tags()?),
+            PromptKind::RemoveTag => ("Remove tag", db.list_tags()?),
+            PromptKind::DefineField => ("Define field (name:type)", vec![]),
+            PromptKind::SetField => (
+                "Set field (name=value)",
+                db.list_fields()?
+                    .into_iter()
+                    .map(|(name, field_type)| format!("{}={}", name, field_type))
+                    .collect(),
+            ),
+            PromptKind::AddComment => {
+                return Ok(format!(" Comment: {}_", self.input));
+            }
+            PromptKind::AddProject => {
+                return Ok(format!(" New project: {}_", self.input));
+            }
+            PromptKind::DescribeProject => {
+                return Ok(format!(" Project description: {}_", self.input));
+            }
+            PromptKind::Estimate => {
+                return Ok(format!(" Estimate (e.g. 1h30m): {}_", self.input));
+            }
+        };
+
+        // Suggest what the user might be typing out.
+        let suggestions: Vec<String> = suggestions
+            .into_iter()
+            .filter(|suggestion| suggestion.starts_with(&self.input))
+            .collect();
+        Ok(format!(
+            " {}: {}_ ({})",
+            label,
+            self.input,
+            suggestions.join(", ")
+        ))
+    }
 }
 
 #[derive(Eq, PartialEq)]
@@ -203,6 +1123,7 @@ enum EditMode {
     List,
     Title,
     Body,
+    Checklist,
 }
 
 type Handler = dyn Fn(&mut State, &database::Database, KeyEvent) -> anyhow::Result<()>;
@@ -213,16 +1134,18 @@ impl EditMode {
         match self {
             List => Title,
             Title => Body,
-            Body => List,
+            Body => Checklist,
+            Checklist => List,
         }
     }
 
     fn prev(&self) -> EditMode {
         use EditMode::*;
         match self {
-            List => Body,
+            List => Checklist,
             Title => List,
             Body => Title,
+            Checklist => Body,
         }
     }
 
@@ -232,6 +1155,7 @@ impl EditMode {
             List => EditMode::handle_event_list,
             Title => EditMode::handle_event_title,
             Body => EditMode::handle_event_body,
+            Checklist => EditMode::handle_event_checklist,
         })
     }
 
@@ -240,21 +1164,307 @@ impl EditMode {
         db: &database::Database,
         event: KeyEvent,
     ) -> anyhow::Result<()> {
-        let tasks = db.list_tasks()?;
+        let rows = state.visible_rows(db)?;
+        let depths: Vec<usize> = rows.iter().map(|row| row.depth).collect();
+        let mut tasks: Vec<Task> = rows.into_iter().map(|row| row.task).collect();
+
+        if state.confirm_delete {
+            state.confirm_delete = false;
+            if event.code == KeyCode::Char('y') && state.current_task < tasks.len() {
+                tasks.remove(state.current_task).delete()?;
+                if state.current_task > 0 && state.current_task >= tasks.len() {
+                    state.current_task -= 1;
+                }
+            }
+            return Ok(());
+        }
 
         match event.code {
+            KeyCode::Up | KeyCode::Down
+                if event.modifiers.contains(KeyModifiers::SHIFT)
+                    && state.sort == SortOrder::Manual
+                    && state.current_task < tasks.len() =>
+            {
+                // Tasks are only reordered among their siblings.
+                let depth = depths[state.current_task];
+                let sibling = if event.code == KeyCode::Up {
+                    (0..state.current_task)
+                        .rev()
+                        .take_while(|i| depths[*i] >= depth)
+                        .find(|i| depths[*i] == depth)
+                } else {
+                    (state.current_task + 1..tasks.len())
+                        .take_while(|i| depths[*i] >= depth)
+                        .find(|i| depths[*i] == depth)
+                };
+
+                if let Some(sibling) = sibling {
+                    let sibling_id = tasks[sibling].id()?;
+                    let new_index = db
+                        .list_tasks()?
+                        .iter()
+                        .position(|task| task.id().ok() == Some(sibling_id));
+                    if let Some(new_index) = new_index {
+                        db.move_task(tasks[state.current_task].id()?, new_index)?;
+                    }
+                }
+            }
             KeyCode::Up => {
                 if state.current_task != 0 {
                     state.current_task -= 1;
                 }
             }
             KeyCode::Down => {
-                if state.current_task < tasks.len() - 1 {
+                if state.current_task + 1 < tasks.len() {
                     state.current_task += 1;
                 }
             }
             KeyCode::Char('a') => {
-                db.add_task()?;
+                let task = db.add_task()?;
+                task.set_project(state.current_project(db)?.as_ref())?;
+                state.current_task = 0;
+            }
+            KeyCode::Char('R') => {
+                let repaired = db.repair()?;
+                let remaining = db.diagnose()?.len();
+                state.message = Some(format!(
+                    "Repaired {} tasks, {} problems remain",
+                    repaired, remaining
+                ));
+            }
+            KeyCode::Char('l') if state.current_task < tasks.len() => {
+                state.prompt = Some(Prompt::new(PromptKind::Estimate));
+            }
+            KeyCode::Char('i') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    let running = match db.running_clock()? {
+                        None => false,
+                        Some((running, _)) => running.id()? == task.id()?,
+                    };
+                    if running {
+                        task.clock_out()?;
+                    } else {
+                        task.clock_in()?;
+                    }
+                }
+            }
+            KeyCode::Char('}') => state.switch_project(db, true)?,
+            KeyCode::Char('{') => state.switch_project(db, false)?,
+            KeyCode::Char('P') => {
+                state.prompt = Some(Prompt::new(PromptKind::AddProject));
+            }
+            KeyCode::Char('D') if state.project.is_some() => {
+                state.prompt = Some(Prompt::new(PromptKind::DescribeProject));
+            }
+            KeyCode::Char('g') => {
+                // Moves the task into the project being viewed, or out of any project.
+                if let Some(task) = tasks.get(state.current_task) {
+                    task.set_project(state.current_project(db)?.as_ref())?;
+                }
+            }
+            KeyCode::Char('O') => {
+                if let Some(project) = state.current_project(db)? {
+                    project.set_status(project.status()?.next())?;
+                }
+            }
+            KeyCode::Char('X') => {
+                if let Some(project) = state.current_project(db)? {
+                    if project.archived()? {
+                        project.unarchive()?;
+                    } else {
+                        project.archive()?;
+                        if !state.show_archived {
+                            state.project = None;
+                        }
+                    }
+                }
+            }
+            KeyCode::Char(' ') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    let status = match task.status()? {
+                        Status::Done => Status::Todo,
+                        _ => Status::Done,
+                    };
+                    task.set_status(status)?;
+                }
+            }
+            KeyCode::Char('p') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    task.set_status(Status::InProgress)?;
+                }
+            }
+            KeyCode::Char('c') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    task.set_status(Status::Cancelled)?;
+                }
+            }
+            KeyCode::Char('h') => {
+                state.hide_finished = !state.hide_finished;
+            }
+            KeyCode::Enter => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    if task.subtasks()?.is_empty() {
+                        return Ok(());
+                    }
+                    let id = task.id()?;
+                    if !state.collapsed.remove(&id) {
+                        state.collapsed.insert(id);
+                    }
+                }
+            }
+            KeyCode::Char('n') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    state.collapsed.remove(&task.id()?);
+                    let subtask = task.add_subtask()?;
+                    state.select(db, subtask.id()?)?;
+                }
+            }
+            KeyCode::Char('>') => {
+                // Like org-mode, demoting a task nests it under its previous sibling.
+                if let Some(task) = tasks.get(state.current_task) {
+                    let depth = depths[state.current_task];
+                    let sibling = (0..state.current_task)
+                        .rev()
+                        .take_while(|i| depths[*i] >= depth)
+                        .find(|i| depths[*i] == depth);
+                    if let Some(sibling) = sibling {
+                        task.move_to(Some(&tasks[sibling]))?;
+                        state.collapsed.remove(&tasks[sibling].id()?);
+                    }
+                }
+            }
+            KeyCode::Char('<') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    if let Some(parent) = task.parent_task()? {
+                        task.move_to(parent.parent_task()?.as_ref())?;
+                    }
+                }
+            }
+            KeyCode::Char('b') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    match state.pending_blocked.take() {
+                        None => state.pending_blocked = Some(task.id()?),
+                        Some(blocked_id) => {
+                            if let Some(blocked) = db.get_task(blocked_id)? {
+                                if let Err(e) = blocked.add_blocker(task) {
+                                    state.message = Some(e.to_string());
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            KeyCode::Char('u') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    task.clear_blockers()?;
+                }
+            }
+            KeyCode::Char('w') => {
+                state.actionable_only = !state.actionable_only;
+            }
+            KeyCode::Esc => {
+                state.pending_blocked = None;
+            }
+            KeyCode::Char('o') => {
+                state.sort = state.sort.next();
+            }
+            KeyCode::Char(c @ '0'..='3') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    task.set_priority(match c {
+                        '1' => Some(Priority::A),
+                        '2' => Some(Priority::B),
+                        '3' => Some(Priority::C),
+                        _ => None,
+                    })?;
+                }
+            }
+            KeyCode::Char('/') => {
+                state.prompt = Some(Prompt::new(PromptKind::TagFilter));
+            }
+            KeyCode::Char('t') if state.current_task < tasks.len() => {
+                state.prompt = Some(Prompt::new(PromptKind::AddTag));
+            }
+            KeyCode::Char('T') if state.current_task < tasks.len() => {
+                state.prompt = Some(Prompt::new(PromptKind::RemoveTag));
+            }
+            KeyCode::Char('F') => {
+                state.prompt = Some(Prompt::new(PromptKind::DefineField));
+            }
+            KeyCode::Char('f') if state.current_task < tasks.len() => {
+                state.prompt = Some(Prompt::new(PromptKind::SetField));
+            }
+            KeyCode::Char('m') if state.current_task < tasks.len() => {
+                state.prompt = Some(Prompt::new(PromptKind::AddComment));
+            }
+            KeyCode::Char('d') if state.current_task < tasks.len() => {
+                state.confirm_delete = true;
+            }
+            KeyCode::Char('A') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    if task.archived()? {
+                        task.unarchive()?;
+                    } else {
+                        task.archive()?;
+                    }
+                }
+            }
+            KeyCode::Char('v') => {
+                state.show_archived = !state.show_archived;
+                state.current_task = 0;
+            }
+            KeyCode::Char('s') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    task.set_scheduled(Local::today().naive_local())?;
+                }
+            }
+            KeyCode::Char('S') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    task.clear_scheduled()?;
+                }
+            }
+            KeyCode::Char('r') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    let scheduled = task
+                        .scheduled()?
+                        .unwrap_or_else(|| Local::today().naive_local());
+                    task.set_recurrence(match task.recurrence()? {
+                        None => Some(Recurrence::Daily),
+                        Some(Recurrence::Daily) => {
+                            Some(Recurrence::Weekly(vec![scheduled.weekday()]))
+                        }
+                        Some(Recurrence::Weekly(_)) => {
+                            Some(Recurrence::Monthly(Some(scheduled.day())))
+                        }
+                        Some(Recurrence::Monthly(_)) => Some(Recurrence::AfterCompletion(7)),
+                        Some(Recurrence::AfterCompletion(_)) => None,
+                    })?;
+                }
+            }
+            KeyCode::Char('e') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    task.set_deadline(Local::today().naive_local())?;
+                }
+            }
+            KeyCode::Char('E') => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    task.clear_deadline()?;
+                }
+            }
+            KeyCode::Char(c @ (']' | '[')) => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    if let Some(deadline) = task.deadline()? {
+                        let offset = if c == ']' { 1 } else { -1 };
+                        task.set_deadline(deadline + Duration::days(offset))?;
+                    }
+                }
+            }
+            KeyCode::Char(c @ ('+' | '-')) => {
+                if let Some(task) = tasks.get(state.current_task) {
+                    if let Some(scheduled) = task.scheduled()? {
+                        let offset = if c == '+' { 1 } else { -1 };
+                        task.set_scheduled(scheduled + Duration::days(offset))?;
+                    }
+                }
             }
             _ => {}
         }
@@ -267,19 +1477,19 @@ impl EditMode {
         db: &database::Database,
         event: KeyEvent,
     ) -> anyhow::Result<()> {
-        let tasks = db.list_tasks()?;
-        if state.current_task >= tasks.len() {
-            return Ok(());
-        }
-        let current_task = &tasks[state.current_task];
-        let current_task_title = current_task.title()?;
+        let current_task = match state.selected(db)? {
+            None => return Ok(()),
+            Some(current_task) => current_task,
+        };
+        let mut current_task_title = current_task.title()?;
 
+        // Text positions count chars, not bytes.
         match event.code {
             KeyCode::Char(c) => {
-                current_task.splice_title(current_task_title.len(), 0, c.to_string())?;
+                current_task.splice_title(current_task_title.chars().count(), 0, c.to_string())?;
             }
-            KeyCode::Backspace => {
-                current_task.splice_title(current_task_title.len() - 1, 1, "")?;
+            KeyCode::Backspace if current_task_title.pop().is_some() => {
+                current_task.splice_title(current_task_title.chars().count(), 1, "")?;
             }
             _ => {}
         }
@@ -292,19 +1502,92 @@ impl EditMode {
         db: &database::Database,
         event: KeyEvent,
     ) -> anyhow::Result<()> {
-        let tasks = db.list_tasks()?;
-        if state.current_task >= tasks.len() {
-            return Ok(());
+        let current_task = match state.selected(db)? {
+            None => return Ok(()),
+            Some(current_task) => current_task,
+        };
+        let mut current_task_body = current_task.body()?;
+
+        match event.code {
+            KeyCode::Char(c) => {
+                current_task.splice_body(current_task_body.chars().count(), 0, c.to_string())?;
+            }
+            KeyCode::Backspace if current_task_body.pop().is_some() => {
+                current_task.splice_body(current_task_body.chars().count(), 1, "")?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn handle_event_checklist(
+        state: &mut State,
+        db: &database::Database,
+        event: KeyEvent,
+    ) -> anyhow::Result<()> {
+        let current_task = match state.selected(db)? {
+            None => return Ok(()),
+            Some(current_task) => current_task,
+        };
+        let checklist = current_task.checklist()?;
+        if state.current_item >= checklist.len() {
+            state.current_item = checklist.len().saturating_sub(1);
         }
-        let current_task = &tasks[state.current_task];
-        let current_task_body = current_task.body()?;
 
+        let shift = event.modifiers.contains(KeyModifiers::SHIFT);
         match event.code {
+            KeyCode::Up if shift && state.current_item > 0 => {
+                current_task.move_checklist_item(state.current_item, state.current_item - 1)?;
+                state.current_item -= 1;
+            }
+            KeyCode::Down if shift && state.current_item + 1 < checklist.len() => {
+                current_task.move_checklist_item(state.current_item, state.current_item + 1)?;
+                state.current_item += 1;
+            }
+            KeyCode::Up => {
+                state.current_item = state.current_item.saturating_sub(1);
+            }
+            KeyCode::Down if state.current_item + 1 < checklist.len() => {
+                state.current_item += 1;
+            }
+            KeyCode::Enter => {
+                current_task.add_checklist_item("")?;
+                state.current_item = checklist.len();
+            }
+            // Plain characters are typed into the item, so toggling needs a modifier.
+            KeyCode::Char('x')
+                if event.modifiers.contains(KeyModifiers::CONTROL)
+                    && state.current_item < checklist.len() =>
+            {
+                current_task.toggle_checklist_item(state.current_item)?;
+            }
             KeyCode::Char(c) => {
-                current_task.splice_body(current_task_body.len(), 0, c.to_string())?;
+                if let Some(item) = checklist.get(state.current_item) {
+                    current_task.splice_checklist_item(
+                        state.current_item,
+                        item.text.chars().count(),
+                        0,
+                        c.to_string(),
+                    )?;
+                }
             }
             KeyCode::Backspace => {
-                current_task.splice_body(current_task_body.len() - 1, 1, "")?;
+                // Backspacing an empty item removes it.
+                if let Some(item) = checklist.get(state.current_item) {
+                    let mut text = item.text.clone();
+                    if text.pop().is_none() {
+                        current_task.remove_checklist_item(state.current_item)?;
+                        state.current_item = state.current_item.saturating_sub(1);
+                    } else {
+                        current_task.splice_checklist_item(
+                            state.current_item,
+                            text.chars().count(),
+                            1,
+                            "",
+                        )?;
+                    }
+                }
             }
             _ => {}
         }
@@ -312,3 +1595,89 @@ impl EditMode {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::minutes(90));
+        assert_eq!(parse_duration("45m").unwrap(), Duration::minutes(45));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::hours(2));
+        assert_eq!(parse_duration("1:30").unwrap(), Duration::minutes(90));
+
+        for input in ["-5m", "-1:30", "1:-30", "abc", "1x", "5", "h", "1:xx"] {
+            assert!(parse_duration(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::minutes(90)), "1:30");
+        assert_eq!(format_duration(Duration::zero()), "0:00");
+        assert_eq!(format_duration(Duration::minutes(-90)), "-1:30");
+        // Seconds are truncated rather than rounded.
+        assert_eq!(format_duration(Duration::seconds(119)), "0:01");
+        assert_eq!(format_duration(Duration::seconds(-119)), "-0:01");
+    }
+
+    fn press(state: State, db: &database::Database, code: KeyCode) -> State {
+        let key = KeyEvent::new(code, KeyModifiers::NONE);
+        state
+            .handle_event(db, controller::Event::Terminal(Event::Key(key)))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_edit_keys() {
+        let db = database::Database::new().unwrap();
+        // Moving around an empty list does nothing.
+        let mut state = press(State::new(), &db, KeyCode::Down);
+        assert_eq!(state.current_task, 0);
+
+        let task = db.add_task().unwrap();
+        task.splice_title(0, 0, "café").unwrap();
+        state.mode = EditMode::Title;
+        state = press(state, &db, KeyCode::Backspace);
+        assert_eq!(task.title().unwrap(), "caf");
+        state = press(state, &db, KeyCode::Char('é'));
+        assert_eq!(task.title().unwrap(), "café");
+        for _ in 0..5 {
+            state = press(state, &db, KeyCode::Backspace);
+        }
+        assert_eq!(task.title().unwrap(), "");
+        assert_eq!(state.message, None);
+    }
+
+    #[test]
+    fn test_format_estimate_report() {
+        let db = database::Database::new().unwrap();
+        let project = db.add_project("work").unwrap();
+        let start = Utc::now();
+        for (estimate, worked) in [(60, 90), (30, 20)] {
+            let task = db.add_task().unwrap();
+            task.set_project(Some(&project)).unwrap();
+            task.set_estimate(Some(Duration::minutes(estimate)))
+                .unwrap();
+            task.clock_in_at(start).unwrap();
+            task.clock_out_at(start + Duration::minutes(worked))
+                .unwrap();
+        }
+        // Tasks without an estimate are left out.
+        db.add_task().unwrap();
+
+        let report = format_estimate_report(&db, GroupBy::Project, start).unwrap();
+        let lines: Vec<Vec<&str>> = report
+            .lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                vec!["project", "tasks", "estimate", "actual", "over"],
+                vec!["work", "2", "1:30", "1:50", "0:20"],
+            ]
+        );
+    }
+}